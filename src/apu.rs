@@ -954,6 +954,7 @@ mod oscillators {
 
 use std::cmp;
 use std::cmp::min;
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
 use cpal::{traits::{HostTrait, DeviceTrait}, StreamConfig, StreamError, Stream, SupportedStreamConfig, SampleRate};
@@ -978,8 +979,18 @@ struct AudioProcessingState {
     right_master_vol: u8,
 
     power_control: bool,
+
+    /// Samples generated since the recorder last took them, while a recording runs.
+    #[serde(skip)]
+    recorded_samples: Option<Vec<f32>>,
+    /// The same samples, waiting for the audio callback to play them.
+    #[serde(skip)]
+    playback: VecDeque<(f32, f32)>,
 }
 
+/// Clock cycles per second, which recorded samples are timed against.
+const CLOCK_RATE: u64 = 4194304;
+
 impl AudioProcessingState {
     pub(crate) fn new() -> Arc<Mutex<AudioProcessingState>> {
         let config = Self::load_config();
@@ -1159,7 +1170,7 @@ impl AudioProcessingState {
         let num_samples = audio.len() / self.num_channels as usize;
 
         for sample_index in 0..num_samples {
-            let generated_samples = self.generate_samples();
+            let generated_samples = self.next_samples();
 
             let first_channel_index = sample_index * self.num_channels as usize;
 
@@ -1178,7 +1189,7 @@ impl AudioProcessingState {
         let num_samples = audio.len() / self.num_channels as usize;
 
         for sample_index in 0..num_samples {
-            let f32_samples = self.generate_samples();
+            let f32_samples = self.next_samples();
 
             let left_sample = (f32_samples.0 * i16::MAX as f32) as i16;
             let right_sample = (f32_samples.1 * i16::MAX as f32) as i16;
@@ -1200,7 +1211,7 @@ impl AudioProcessingState {
         let num_samples = audio.len() / self.num_channels as usize;
 
         for sample_index in 0..num_samples {
-            let f32_samples = self.generate_samples();
+            let f32_samples = self.next_samples();
 
             let left_sample = ((f32_samples.0 + 1.0) * u16::MAX as f32) as u16;
             let right_sample = ((f32_samples.1 + 1.0) * u16::MAX as f32) as u16;
@@ -1222,6 +1233,28 @@ impl AudioProcessingState {
         Logger::error(format!("Audio Error: {:?}", error));
     }
 
+    fn next_samples(&mut self) -> (f32, f32) {
+        // While recording, the emulation generates the samples and the callback only plays them
+        if self.recorded_samples.is_some() {
+            self.playback.pop_front().unwrap_or_default()
+        } else {
+            self.generate_samples()
+        }
+    }
+
+    fn record_samples(&mut self) {
+        let samples = self.generate_samples();
+        if let Some(recorded) = self.recorded_samples.as_mut() {
+            recorded.extend([samples.0, samples.1]);
+        }
+
+        // Samples generated faster than real time are dropped from the playback
+        if self.playback.len() >= self.sample_rate as usize / 4 {
+            self.playback.pop_front();
+        }
+        self.playback.push_back(samples);
+    }
+
     fn generate_samples(&mut self) -> (f32, f32) {
         if !self.power_control {
             return (0.0, 0.0);
@@ -1278,19 +1311,53 @@ pub struct AudioProcessingUnit {
     state: Arc<Mutex<AudioProcessingState>>,
     #[serde(skip)]
     pub(crate) stream: Option<Stream>,
+    /// Sample rate of the running recording, and the clock cycles elapsed since its last
+    /// sample, multiplied by that rate.
+    #[serde(skip)]
+    recording: Option<(u32, u64)>,
 }
 
 impl AudioProcessingUnit {
     pub(crate) fn new() -> AudioProcessingUnit {
         let state = AudioProcessingState::new();
         let stream = AudioProcessingState::load_stream(&state);
-        AudioProcessingUnit { state, stream }
+        AudioProcessingUnit { state, stream, recording: None }
     }
 
     pub(crate) fn init(&mut self) {
         self.stream = AudioProcessingState::load_stream(&self.state);
     }
 
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.state.lock().unwrap().sample_rate
+    }
+
+    /// Starts or stops generating the samples in emulated time, for the recorder to take.
+    pub(crate) fn set_recording(&mut self, recording: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.recorded_samples = recording.then(Vec::new);
+        state.playback.clear();
+        self.recording = recording.then_some((state.sample_rate, 0));
+    }
+
+    /// Generates the samples due after the given clock cycles, while a recording runs.
+    pub(crate) fn cycle(&mut self, ticks: usize) {
+        if let Some((sample_rate, elapsed)) = self.recording.as_mut() {
+            *elapsed += ticks as u64 * *sample_rate as u64;
+            if *elapsed >= CLOCK_RATE {
+                let mut state = self.state.lock().unwrap();
+                while *elapsed >= CLOCK_RATE {
+                    state.record_samples();
+                    *elapsed -= CLOCK_RATE;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn take_recorded_samples(&self) -> Vec<f32> {
+        self.state.lock().unwrap().recorded_samples.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub(crate) fn write(&mut self, address: usize, value: u8) -> bool {
         if !(0xFF10..=0xFF3F).contains(&address) {
             false
@@ -1307,4 +1374,27 @@ impl AudioProcessingUnit {
             Some(self.state.lock().unwrap().read_register(address))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_samples_in_emulated_time() {
+        let state = AudioProcessingState { sample_rate: 44100, num_channels: 2, ..Default::default() };
+        let mut apu = AudioProcessingUnit { state: Arc::new(Mutex::new(state)), ..Default::default() };
+        apu.cycle(4);
+        assert!(apu.take_recorded_samples().is_empty());
+
+        apu.set_recording(true);
+        for _ in 0..CLOCK_RATE / 4 {
+            apu.cycle(4);
+        }
+        // One left and right pair per sample, without any audio callback running
+        assert_eq!(apu.take_recorded_samples().len(), 2 * 44100);
+
+        apu.set_recording(false);
+        apu.cycle(4 * 1024);
+        assert!(apu.take_recorded_samples().is_empty());
+    }
+}
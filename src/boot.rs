@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mmu::MemoryManagementUnit;
+use crate::model::Model;
+
+/// Machine cycles the DMG boot ROM takes from power on until it unmaps itself. DIV starts at 0
+/// and the boot ROM leaves its 16 bit counter at 0xABCC (boot_div-dmgABCmgb), which gives the
+/// clock count modulo 0x10000. The multiple of 0x10000 is the one closest to the PPU clock of
+/// 23,440,377 the emulator used to start from after the boot ROM, about 5.6 seconds.
+const BOOT_CYCLES: usize = 5_860_083;
+/// Machine cycles spent clearing VRAM and unpacking the logo with the LCD off. Chosen so the
+/// boot ends in line 153, 100 clocks before line 0, as the PPU is left after the boot ROM.
+const SETUP_CYCLES: usize = 66_630;
+/// The logo scrolls down one line per step, then stays still for the remaining steps.
+const SCROLL_STEPS: usize = 0x64;
+const STEPS: usize = SCROLL_STEPS + 0x20;
+/// Each step waits for a few VBlanks by polling LY.
+const STEP_CYCLES: usize = (BOOT_CYCLES - SETUP_CYCLES) / STEPS;
+
+/// The two notes of the chime, played on the steps the boot ROM plays them on.
+const CHIME: [(usize, u8); 2] = [(0x61, 0x83), (0x63, 0xC1)];
+
+/// The ® next to the logo isn't part of the cartridge header.
+const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// Plays the boot animation without a boot ROM, drawing the logo from the cartridge header.
+/// The CPU doesn't run while booting, the boot sequence drives the hardware directly and
+/// hands over to the cartridge once the real boot ROM would. Only the DMG and MGB boot ROM
+/// is reproduced, the other models start the cartridge right away.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HleBoot {
+    cycles: usize,
+}
+
+impl HleBoot {
+    /// Whether the boot sequence of the model is reproduced.
+    pub fn supports(model: Model) -> bool {
+        matches!(model, Model::Dmg | Model::Mgb)
+    }
+
+    /// Resets the hardware to its power on state and draws the logo into VRAM.
+    pub fn new(mmu: &mut MemoryManagementUnit) -> Self {
+        mmu.internal_write(0xFF40, 0x00);
+        mmu.reset_divider();
+        mmu.ppu.vram.fill(0);
+
+        // Sound setup for the chime
+        mmu.internal_write(0xFF26, 0x80);
+        mmu.internal_write(0xFF11, 0x80);
+        mmu.internal_write(0xFF12, 0xF3);
+        mmu.internal_write(0xFF25, 0xF3);
+        mmu.internal_write(0xFF24, 0x77);
+
+        mmu.internal_write(0xFF47, 0xFC);
+
+        // Every nibble of the logo becomes two rows of a tile, with its pixels doubled
+        let logo: Vec<u8> = (0x104..0x134).map(|address| mmu.internal_read(address)).collect();
+        let rows = logo
+            .into_iter()
+            .flat_map(|byte| [byte >> 4, byte >> 4, byte & 0x0F, byte & 0x0F])
+            .map(Self::double_pixels)
+            .chain(REGISTERED);
+        for (index, row) in rows.enumerate() {
+            mmu.ppu.vram[0x10 + index * 2] = row;
+        }
+
+        // Logo tiles 0x01-0x18 over two lines, with the ® after the first one
+        for tile in 0x01..=0x0C {
+            mmu.ppu.vram[0x1903 + tile] = tile as u8;
+            mmu.ppu.vram[0x1923 + tile] = tile as u8 + 0x0C;
+        }
+        mmu.ppu.vram[0x1910] = 0x19;
+
+        Self::default()
+    }
+
+    fn double_pixels(nibble: u8) -> u8 {
+        (0..4).fold(0, |row, bit| row | (((nibble >> bit) & 1) * 0b11) << (bit * 2))
+    }
+
+    /// Runs one machine cycle of the boot sequence. Returns whether the cartridge can start.
+    pub fn machine_cycle(&mut self, mmu: &mut MemoryManagementUnit) -> bool {
+        self.cycles += 1;
+
+        if self.cycles == SETUP_CYCLES {
+            mmu.internal_write(0xFF42, SCROLL_STEPS as u8);
+            mmu.internal_write(0xFF40, 0x91);
+        }
+
+        let elapsed = self.cycles.saturating_sub(SETUP_CYCLES);
+        if self.cycles > SETUP_CYCLES && elapsed.is_multiple_of(STEP_CYCLES) {
+            self.step(mmu, elapsed / STEP_CYCLES - 1);
+        }
+
+        self.cycles == BOOT_CYCLES
+    }
+
+    fn step(&self, mmu: &mut MemoryManagementUnit, step: usize) {
+        if let Some(&(_, frequency)) = CHIME.iter().find(|(at, _)| *at == step) {
+            mmu.internal_write(0xFF13, frequency);
+            mmu.internal_write(0xFF14, 0x87);
+        }
+
+        if step < SCROLL_STEPS {
+            let scy = mmu.internal_read(0xFF42);
+            mmu.internal_write(0xFF42, scy.wrapping_sub(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::Gameboy;
+    use crate::register::Register;
+
+    /// Registers the boot ROM leaves behind, besides the CPU ones.
+    const REGISTERS: [usize; 9] = [
+        0xFF04, 0xFF0F, 0xFF26, 0xFF40, 0xFF41, 0xFF42, 0xFF43, 0xFF44, 0xFF47,
+    ];
+
+    #[test]
+    fn hands_over_in_post_boot_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x108].copy_from_slice(&[0xCE, 0xED, 0x66, 0x66]);
+        let skipped = MemoryManagementUnit::for_rom(rom.clone(), Model::Dmg);
+        let mut gameboy = Gameboy::new(MemoryManagementUnit::for_rom(rom, Model::Dmg));
+        gameboy.hle_boot();
+
+        let mut cycles = 0;
+        while gameboy.boot.is_some() {
+            cycles += gameboy.cycle() as usize;
+        }
+
+        assert_eq!(cycles, BOOT_CYCLES);
+        assert_eq!((BOOT_CYCLES * 4) as u16, Model::Dmg.divider().unwrap());
+        assert_eq!(gameboy.reg, Register::new(false, Model::Dmg));
+        for register in REGISTERS {
+            assert_eq!(
+                gameboy.mmu.internal_read(register),
+                skipped.internal_read(register),
+                "{register:04X}"
+            );
+        }
+        assert_eq!(gameboy.mmu.ppu.state, skipped.ppu.state);
+        assert_eq!(
+            gameboy.mmu.ppu.next_ticks - gameboy.mmu.ppu.ticks,
+            skipped.ppu.next_ticks - skipped.ppu.ticks
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::read;
+
+use crate::model::Model;
+
+/// CRC32 of the known boot ROM dumps.
+const KNOWN_BOOT_ROMS: [(u32, Model); 7] = [
+    (0xC2F5CC97, Model::Dmg0),
+    (0x59C8598E, Model::Dmg),
+    (0xE6920754, Model::Mgb),
+    (0xEC8A83B9, Model::Sgb),
+    (0x53D0DD63, Model::Sgb2),
+    (0x41884E46, Model::Cgb),
+    (0xFFD6B0F1, Model::Agb),
+];
+
+/// Size of the DMG and SGB boot ROMs, mapped at 0x0000-0x00FF.
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+/// Size of the CGB and AGB boot ROMs, also mapped at 0x0200-0x08FF around the cartridge header.
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Reads a boot ROM dump, returning it with the model it belongs to.
+pub fn load(path: &str) -> Result<(Vec<u8>, Model), String> {
+    let data = read(path).map_err(|e| format!("Unable to read boot ROM {path}: {e}"))?;
+    let model = identify(&data).map_err(|e| format!("Invalid boot ROM {path}: {e}"))?;
+    Ok((data, model))
+}
+
+pub fn identify(data: &[u8]) -> Result<Model, String> {
+    if data.len() != DMG_BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+        return Err(format!(
+            "expected {DMG_BOOT_ROM_SIZE} or {CGB_BOOT_ROM_SIZE} bytes, got {}",
+            data.len()
+        ));
+    }
+
+    let crc = crc32fast::hash(data);
+    KNOWN_BOOT_ROMS
+        .iter()
+        .find(|(known, _)| *known == crc)
+        .map(|&(_, model)| model)
+        .ok_or_else(|| format!("unknown image with CRC32 {crc:08X}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MemoryManagementUnit;
+
+    #[test]
+    fn rejects_wrong_size() {
+        assert_eq!(
+            identify(&[0; 0x200]),
+            Err("expected 256 or 2304 bytes, got 512".to_string())
+        );
+        assert!(identify(&[]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_crc() {
+        let crc = crc32fast::hash(&[0; DMG_BOOT_ROM_SIZE]);
+        assert_eq!(
+            identify(&[0; DMG_BOOT_ROM_SIZE]),
+            Err(format!("unknown image with CRC32 {crc:08X}"))
+        );
+        assert!(identify(&[0xFF; CGB_BOOT_ROM_SIZE]).is_err());
+    }
+
+    #[test]
+    fn known_crcs_are_unique() {
+        for (index, (crc, _)) in KNOWN_BOOT_ROMS.iter().enumerate() {
+            assert!(KNOWN_BOOT_ROMS[index + 1..].iter().all(|(other, _)| other != crc));
+        }
+    }
+
+    #[test]
+    fn cgb_boot_rom_unmaps_on_0x11() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x12;
+        rom[0x0200] = 0x34;
+        let mut mmu = MemoryManagementUnit::for_rom(rom, Model::Cgb);
+        mmu.boot_rom = Some(vec![0xAA; CGB_BOOT_ROM_SIZE]);
+        assert_eq!(mmu.read(0x0000u16), 0xAA);
+        assert_eq!(mmu.read(0x0200u16), 0xAA);
+
+        mmu.write(0xFF50u16, 0x11u8);
+        assert_eq!(mmu.boot_rom, None);
+        assert_eq!(mmu.read(0x0000u16), 0x12);
+        assert_eq!(mmu.read(0x0200u16), 0x34);
+    }
+}
@@ -7,6 +7,10 @@ pub struct Cartridge {
     pub(crate) title: Option<String>,
     publisher: Option<String>,
     pub(crate) mbc: u8,
+    /// Supports the CGB features, from the CGB flag at 0x143
+    pub(crate) cgb: bool,
+    /// Supports the SGB functions, from the SGB flag at 0x146 and the old licensee code
+    pub(crate) sgb: bool,
     rom_size: usize,
     ram_size: u8,
     destination: u8,
@@ -27,6 +31,8 @@ impl Cartridge {
             title: from_utf8(title.as_slice()).map(|t| t.to_string()).ok(),
             publisher: from_utf8(&rom[0x144..=0x145]).map(|t| t.to_string()).ok(),
             mbc: rom[0x147],
+            cgb: rom[0x143] & 0x80 != 0,
+            sgb: rom[0x146] == 0x03 && rom[0x14B] == 0x33,
             rom_size: 32 << rom[0x148],
             ram_size: rom[0x149],
             destination: rom[0x14A],
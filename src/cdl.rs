@@ -0,0 +1,101 @@
+use std::fs::{read, write};
+use std::path::PathBuf;
+
+use crate::logger::Logger;
+
+/// How a ROM byte was accessed, stored as bit flags in the code/data log.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RomAccess {
+    /// First byte of an executed instruction.
+    Opcode = 0x01,
+    /// Remaining bytes of an executed instruction.
+    Operand = 0x02,
+    /// Byte read by an instruction or a DMA transfer.
+    Data = 0x04,
+}
+
+/// Records which ROM bytes ran as code and which were read as data.
+/// The log is a flat file with one flag byte per ROM byte and no header.
+pub struct CodeDataLogger {
+    path: PathBuf,
+    flags: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    /// Continues the log stored at the given path if it matches the ROM size, or starts a new one.
+    pub fn new(path: PathBuf, rom_size: usize) -> Self {
+        let flags = match read(&path) {
+            Ok(flags) if flags.len() == rom_size => {
+                Logger::info(format!("Continuing code/data log {}", path.display()));
+                flags
+            }
+            Ok(_) => {
+                Logger::error(format!(
+                    "Code/data log {} doesn't match the ROM size, starting a new one",
+                    path.display()
+                ));
+                vec![0; rom_size]
+            }
+            Err(_) => vec![0; rom_size],
+        };
+
+        Self { path, flags }
+    }
+
+    pub fn log(&mut self, rom_address: usize, access: RomAccess) {
+        if let Some(flags) = self.flags.get_mut(rom_address) {
+            *flags |= access as u8;
+        }
+    }
+
+    pub fn save(&self) {
+        let code = self.flags.iter().filter(|&&f| f & 0x03 != 0).count();
+        let data = self.flags.iter().filter(|&&f| f & 0x04 != 0).count();
+        match write(&self.path, &self.flags) {
+            Ok(()) => Logger::info(format!(
+                "Code/data log saved to {}: {} code bytes, {} data bytes out of {}",
+                self.path.display(),
+                code,
+                data,
+                self.flags.len()
+            )),
+            Err(e) => Logger::error(format!(
+                "Unable to save code/data log {}: {e}",
+                self.path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MemoryManagementUnit;
+    use crate::model::Model;
+
+    #[test]
+    fn logs_accesses_through_switched_bank() {
+        // 64 KiB MBC1 cart
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        let mut mmu = MemoryManagementUnit::for_rom(rom, Model::Dmg);
+        let path = std::env::temp_dir().join(format!("iron_boy_{}_test.cdl", std::process::id()));
+        mmu.cdl = Some(CodeDataLogger::new(path, 0x10000));
+
+        mmu.fetch_opcode(0x0150);
+        mmu.write(0x2000u16, 3u8);
+        mmu.fetch_opcode(0x4000);
+        mmu.fetch_operand(0x4001);
+        mmu.read(0x4002u16);
+        mmu.read(0x4000u16);
+        mmu.read(0xC000u16);
+
+        let flags = &mmu.cdl.as_ref().unwrap().flags;
+        assert_eq!(flags[0x0150], RomAccess::Opcode as u8);
+        assert_eq!(flags[0xC000], RomAccess::Opcode as u8 | RomAccess::Data as u8);
+        assert_eq!(flags[0xC001], RomAccess::Operand as u8);
+        assert_eq!(flags[0xC002], RomAccess::Data as u8);
+        assert_eq!(flags.iter().filter(|&&flag| flag != 0).count(), 4);
+    }
+}
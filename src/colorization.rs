@@ -0,0 +1,294 @@
+/// Colors the CGB boot ROM picks palettes from, as RGB555.
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+/// OBJ0, OBJ1 and BG palettes of every combination, as the index of their first color in
+/// `PALETTES`. A few of them start halfway through a palette, mixing two of them.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [4 * 4, 4 * 4, 29 * 4],
+    [18 * 4, 18 * 4, 18 * 4],
+    [20 * 4, 20 * 4, 20 * 4],
+    [24 * 4, 24 * 4, 24 * 4],
+    [9 * 4, 9 * 4, 9 * 4],
+    [0, 0, 0],
+    [27 * 4, 27 * 4, 27 * 4],
+    [5 * 4, 5 * 4, 5 * 4],
+    [12 * 4, 12 * 4, 12 * 4],
+    [26 * 4, 26 * 4, 26 * 4],
+    [16 * 4, 8 * 4, 8 * 4],
+    [4 * 4, 28 * 4, 28 * 4],
+    [4 * 4, 2 * 4, 2 * 4],
+    [3 * 4, 4 * 4, 4 * 4],
+    [4 * 4, 29 * 4, 29 * 4],
+    [28 * 4, 4 * 4, 28 * 4],
+    [2 * 4, 17 * 4, 2 * 4],
+    [16 * 4, 16 * 4, 8 * 4],
+    [4 * 4, 4 * 4, 7 * 4],
+    [4 * 4, 4 * 4, 18 * 4],
+    [4 * 4, 4 * 4, 20 * 4],
+    [19 * 4, 19 * 4, 9 * 4],
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    [17 * 4, 17 * 4, 2 * 4],
+    [4 * 4, 4 * 4, 2 * 4],
+    [4 * 4, 4 * 4, 3 * 4],
+    [28 * 4, 28 * 4, 0],
+    [3 * 4, 3 * 4, 0],
+    [0, 0, 4],
+    [18 * 4, 22 * 4, 18 * 4],
+    [20 * 4, 22 * 4, 20 * 4],
+    [24 * 4, 22 * 4, 24 * 4],
+    [16 * 4, 22 * 4, 8 * 4],
+    [17 * 4, 4 * 4, 13 * 4],
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    [19 * 4, 22 * 4, 9 * 4],
+    [16 * 4, 28 * 4, 10 * 4],
+    [4 * 4, 23 * 4, 28 * 4],
+    [17 * 4, 22 * 4, 2 * 4],
+    [4 * 4, 0, 2 * 4],
+    [4 * 4, 28 * 4, 3 * 4],
+    [28 * 4, 3 * 4, 0],
+    [3 * 4, 28 * 4, 4 * 4],
+    [21 * 4, 28 * 4, 4 * 4],
+    [3 * 4, 28 * 4, 0],
+    [25 * 4, 3 * 4, 28 * 4],
+    [0, 28 * 4, 8 * 4],
+    [4 * 4, 3 * 4, 28 * 4],
+    [28 * 4, 3 * 4, 6 * 4],
+    [4 * 4, 28 * 4, 29 * 4],
+];
+
+/// Sums of the title bytes of the cartridges with their own palettes.
+const TITLE_CHECKSUMS: [u8; 65] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E,
+    0x70, 0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15,
+    0xFF, 0x97, 0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0,
+    0x8B, 0xF0, 0xCE, 0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD,
+    0x5D, 0x6D, 0x67, 0x3F, 0x6B,
+];
+
+/// Checksums shared by several titles, told apart by the 4th letter of the title.
+const DUPLICATE_CHECKSUMS: [(u8, u8); 29] = [
+    (0xB3, b'B'),
+    (0x46, b'E'),
+    (0x28, b'F'),
+    (0xA5, b'A'),
+    (0xC6, b'A'),
+    (0xD3, b'R'),
+    (0x27, b'B'),
+    (0x61, b'E'),
+    (0x18, b'K'),
+    (0x66, b'E'),
+    (0x6A, b'K'),
+    (0xBF, b' '),
+    (0x0D, b'R'),
+    (0xF4, b'-'),
+    (0xB3, b'U'),
+    (0x46, b'R'),
+    (0x28, b'A'),
+    (0xA5, b'R'),
+    (0xC6, b' '),
+    (0xD3, b'I'),
+    (0x27, b'N'),
+    (0x61, b'A'),
+    (0x18, b'I'),
+    (0x66, b'L'),
+    (0x6A, b'I'),
+    (0xBF, b'C'),
+    (0x0D, b'E'),
+    (0xF4, b' '),
+    (0xB3, b'R'),
+];
+
+/// Combination used by every title in `TITLE_CHECKSUMS`, followed by `DUPLICATE_CHECKSUMS`.
+const TITLE_COMBINATIONS: [usize; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5,
+    29, 5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42,
+    6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0,
+    19, 34, 23, 18, 29,
+];
+
+/// Combinations selected by holding a direction, alone or with A or B, while the logo is shown.
+/// Indexed by the direction (right, left, up, down), then by the action button (none, A, B).
+const KEY_COMBINATIONS: [[usize; 3]; 4] = [[1, 0, 6], [48, 40, 7], [5, 43, 28], [8, 3, 49]];
+
+/// Combination used for cartridges from other publishers and unknown titles.
+const DEFAULT_COMBINATION: usize = 0;
+
+/// Background and object palettes the CGB boot ROM colorizes a DMG cartridge with.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DmgPalettes {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl DmgPalettes {
+    /// Picks the palettes for the cartridge header (0x0000-0x014F) the way the CGB boot ROM
+    /// does. `action` and `direction` hold the pressed buttons, one bit each as in P1.
+    pub fn for_cartridge(header: &[u8], action: u8, direction: u8) -> Self {
+        let combination = match Self::held_combination(action, direction) {
+            Some(combination) => combination,
+            None if Self::nintendo(header) => Self::title_combination(header),
+            None => DEFAULT_COMBINATION,
+        };
+        Self::combination(combination)
+    }
+
+    fn combination(combination: usize) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS[combination].map(|first| {
+            let mut palette = [0; 4];
+            for (i, color) in palette.iter_mut().enumerate() {
+                *color = PALETTES[(first + i) / 4][(first + i) % 4];
+            }
+            palette
+        });
+        Self { bg, obj0, obj1 }
+    }
+
+    fn held_combination(action: u8, direction: u8) -> Option<usize> {
+        let direction = (0..4).find(|bit| direction & (1 << bit) != 0)?;
+        let button = match action & 0b11 {
+            0 => 0,
+            0b01 | 0b11 => 1,
+            _ => 2,
+        };
+        Some(KEY_COMBINATIONS[direction][button])
+    }
+
+    /// Only cartridges published by Nintendo are looked up by title.
+    fn nintendo(header: &[u8]) -> bool {
+        match header[0x14B] {
+            0x01 => true,
+            0x33 => &header[0x144..=0x145] == b"01",
+            _ => false,
+        }
+    }
+
+    fn title_combination(header: &[u8]) -> usize {
+        let checksum = header[0x134..=0x143]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let fourth_letter = header[0x137];
+
+        let unique = TITLE_CHECKSUMS.iter().position(|&c| c == checksum);
+        let duplicate = || {
+            DUPLICATE_CHECKSUMS
+                .iter()
+                .position(|&entry| entry == (checksum, fourth_letter))
+                .map(|index| TITLE_CHECKSUMS.len() + index)
+        };
+
+        unique
+            .or_else(duplicate)
+            .map_or(DEFAULT_COMBINATION, |index| TITLE_COMBINATIONS[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut header = vec![0; 0x150];
+        header[0x134..0x134 + title.len()].copy_from_slice(title);
+        header[0x14B] = licensee;
+        header
+    }
+
+    /// Title starting with the given letters, padded to the given checksum.
+    fn title_with_checksum(letters: &[u8; 4], checksum: u8) -> Vec<u8> {
+        let sum = letters
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        [letters.as_slice(), &[checksum.wrapping_sub(sum)]].concat()
+    }
+
+    #[test]
+    fn looks_up_unique_title() {
+        let red = DmgPalettes::for_cartridge(&header(b"POKEMON RED", 0x01), 0, 0);
+        assert_eq!(red.bg, PALETTES[4]);
+        assert_eq!(red, DmgPalettes::combination(13));
+    }
+
+    #[test]
+    fn tells_duplicate_checksums_apart_by_fourth_letter() {
+        // POKEMON BLUE shares its checksum with a title whose 4th letter is A
+        let blue = DmgPalettes::for_cartridge(&header(b"POKEMON BLUE", 0x01), 0, 0);
+        assert_eq!(blue.bg, PALETTES[28]);
+        assert_eq!(blue, DmgPalettes::combination(11));
+
+        let other =
+            DmgPalettes::for_cartridge(&header(&title_with_checksum(b"ABCA", 0x61), 0x01), 0, 0);
+        assert_eq!(other, DmgPalettes::combination(41));
+
+        let unknown =
+            DmgPalettes::for_cartridge(&header(&title_with_checksum(b"ABCZ", 0x61), 0x01), 0, 0);
+        assert_eq!(unknown, DmgPalettes::combination(DEFAULT_COMBINATION));
+    }
+
+    #[test]
+    fn only_looks_up_nintendo_titles() {
+        let other_publisher = DmgPalettes::for_cartridge(&header(b"POKEMON RED", 0x08), 0, 0);
+        assert_eq!(
+            other_publisher,
+            DmgPalettes::combination(DEFAULT_COMBINATION)
+        );
+
+        let mut new_licensee = header(b"POKEMON RED", 0x33);
+        new_licensee[0x144..=0x145].copy_from_slice(b"01");
+        assert_eq!(
+            DmgPalettes::for_cartridge(&new_licensee, 0, 0),
+            DmgPalettes::combination(13)
+        );
+    }
+
+    #[test]
+    fn held_buttons_override_title() {
+        let header = header(b"POKEMON RED", 0x01);
+        // Right alone, up with A and down with B
+        assert_eq!(
+            DmgPalettes::for_cartridge(&header, 0, 0b0001),
+            DmgPalettes::combination(1)
+        );
+        assert_eq!(
+            DmgPalettes::for_cartridge(&header, 0b01, 0b0100),
+            DmgPalettes::combination(43)
+        );
+        assert_eq!(
+            DmgPalettes::for_cartridge(&header, 0b10, 0b1000),
+            DmgPalettes::combination(49)
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::mmu::MemoryManagementUnit;
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STACK: [&str; 4] = ["bc", "de", "hl", "af"];
+const R16_MEMORY: [&str; 4] = ["[bc]", "[de]", "[hl+]", "[hl-]"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ACCUMULATOR: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+const SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+/// Decodes the instruction starting with the given bytes at `pc` into RGBDS syntax, returning
+/// it along with its size. Jump targets and memory addresses are named after the label at that
+/// address, if any.
+pub fn disassemble(
+    bytes: [u8; 3],
+    pc: u16,
+    label: impl Fn(u16) -> Option<String>,
+) -> (String, u16) {
+    let [opcode, n8, high] = bytes;
+    let n16 = u16::from_le_bytes([n8, high]);
+    let e8 = n8 as i8;
+    let address = |address: u16| label(address).unwrap_or_else(|| format!("${address:04X}"));
+    let relative = address(pc.wrapping_add(2).wrapping_add(e8 as u16));
+
+    let (x, y, z) = (
+        (opcode >> 6) as usize,
+        ((opcode >> 3) & 0x07) as usize,
+        (opcode & 0x07) as usize,
+    );
+    let (p, q) = (y >> 1, y & 0x01);
+
+    let text = match (x, z) {
+        (0, 0) => match y {
+            0 => "nop".to_string(),
+            1 => format!("ld [{}], sp", address(n16)),
+            2 => "stop".to_string(),
+            3 => format!("jr {relative}"),
+            _ => format!("jr {}, {relative}", CONDITIONS[y - 4]),
+        },
+        (0, 1) if q == 0 => format!("ld {}, {}", R16[p], address(n16)),
+        (0, 1) => format!("add hl, {}", R16[p]),
+        (0, 2) if q == 0 => format!("ld {}, a", R16_MEMORY[p]),
+        (0, 2) => format!("ld a, {}", R16_MEMORY[p]),
+        (0, 3) if q == 0 => format!("inc {}", R16[p]),
+        (0, 3) => format!("dec {}", R16[p]),
+        (0, 4) => format!("inc {}", R8[y]),
+        (0, 5) => format!("dec {}", R8[y]),
+        (0, 6) => format!("ld {}, ${n8:02X}", R8[y]),
+        (0, _) => ACCUMULATOR[y].to_string(),
+        (1, 6) if y == 6 => "halt".to_string(),
+        (1, _) => format!("ld {}, {}", R8[y], R8[z]),
+        (2, _) => format!("{} a, {}", ALU[y], R8[z]),
+        (3, 0) => match y {
+            4 => format!("ldh [{}], a", address(0xFF00 | n8 as u16)),
+            5 => format!("add sp, {e8}"),
+            6 => format!("ldh a, [{}]", address(0xFF00 | n8 as u16)),
+            7 => format!("ld hl, sp{e8:+}"),
+            _ => format!("ret {}", CONDITIONS[y]),
+        },
+        (3, 1) if q == 0 => format!("pop {}", R16_STACK[p]),
+        (3, 1) => ["ret", "reti", "jp hl", "ld sp, hl"][p].to_string(),
+        (3, 2) => match y {
+            4 => "ldh [c], a".to_string(),
+            5 => format!("ld [{}], a", address(n16)),
+            6 => "ldh a, [c]".to_string(),
+            7 => format!("ld a, [{}]", address(n16)),
+            _ => format!("jp {}, {}", CONDITIONS[y], address(n16)),
+        },
+        (3, 3) if y == 0 => format!("jp {}", address(n16)),
+        (3, 3) if y == 1 => {
+            let (x, y, z) = (
+                (n8 >> 6) as usize,
+                ((n8 >> 3) & 0x07) as usize,
+                (n8 & 0x07) as usize,
+            );
+            match x {
+                0 => format!("{} {}", SHIFTS[y], R8[z]),
+                1 => format!("bit {y}, {}", R8[z]),
+                2 => format!("res {y}, {}", R8[z]),
+                _ => format!("set {y}, {}", R8[z]),
+            }
+        }
+        (3, 3) if y == 6 => "di".to_string(),
+        (3, 3) if y == 7 => "ei".to_string(),
+        (3, 4) if y < 4 => format!("call {}, {}", CONDITIONS[y], address(n16)),
+        (3, 5) if q == 0 => format!("push {}", R16_STACK[p]),
+        (3, 5) if p == 0 => format!("call {}", address(n16)),
+        (3, 6) => format!("{} a, ${n8:02X}", ALU[y]),
+        (3, 7) => format!("rst ${:02X}", y * 8),
+        _ => format!("db ${opcode:02X}"),
+    };
+
+    (text, size(opcode))
+}
+
+/// Size in bytes of the instruction starting with the given opcode.
+fn size(opcode: u8) -> u16 {
+    match opcode {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2
+        | 0xD4 | 0xDA | 0xDC | 0xEA | 0xFA => 3,
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E | 0x18 | 0x20 | 0x28 | 0x30
+        | 0x38 | 0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE | 0xE0 | 0xF0 | 0xE8
+        | 0xF8 | 0xCB => 2,
+        _ => 1,
+    }
+}
+
+/// Lists the given amount of instructions starting at `pc`, one per line, as currently mapped in
+/// memory. Each line starts with the address and the closest label.
+pub fn listing(mmu: &MemoryManagementUnit, pc: u16, count: usize) -> String {
+    let label = |address: u16| {
+        let symbols = mmu.symbols.as_ref()?;
+        symbols
+            .label(mmu.bank(address), address)
+            .map(str::to_string)
+    };
+
+    let mut lines = vec![];
+    let mut address = pc;
+    for _ in 0..count {
+        let bytes =
+            [0, 1, 2].map(|offset| mmu.internal_read(address.wrapping_add(offset) as usize));
+        let (text, size) = disassemble(bytes, address, label);
+        let hex: Vec<String> = bytes[..size as usize]
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        lines.push(format!(
+            "{:<32} {:<9} {text}",
+            mmu.describe_address(address),
+            hex.join(" ")
+        ));
+        address = address.wrapping_add(size);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_labels(_: u16) -> Option<String> {
+        None
+    }
+
+    fn labels(address: u16) -> Option<String> {
+        match address {
+            0x0150 => Some("Start".to_string()),
+            0x4000 => Some("BankedCode".to_string()),
+            0xC000 => Some("wBuffer".to_string()),
+            0xFF80 => Some("hCounter".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn decodes_every_opcode_group() {
+        let cases: [([u8; 3], &str, u16); 16] = [
+            ([0x00, 0, 0], "nop", 1),
+            ([0x01, 0x34, 0x12], "ld bc, $1234", 3),
+            ([0x22, 0, 0], "ld [hl+], a", 1),
+            ([0x36, 0x7F, 0], "ld [hl], $7F", 2),
+            ([0x76, 0, 0], "halt", 1),
+            ([0x78, 0, 0], "ld a, b", 1),
+            ([0x96, 0, 0], "sub a, [hl]", 1),
+            ([0xC1, 0, 0], "pop bc", 1),
+            ([0xE8, 0xFE, 0], "add sp, -2", 2),
+            ([0xF8, 0x05, 0], "ld hl, sp+5", 2),
+            ([0xE2, 0, 0], "ldh [c], a", 1),
+            ([0xCB, 0x37, 0], "swap a", 2),
+            ([0xCB, 0x7E, 0], "bit 7, [hl]", 2),
+            ([0xCB, 0xC1, 0], "set 0, c", 2),
+            ([0xEF, 0, 0], "rst $28", 1),
+            ([0xD3, 0, 0], "db $D3", 1),
+        ];
+        for (bytes, text, size) in cases {
+            assert_eq!(
+                disassemble(bytes, 0x0100, no_labels),
+                (text.to_string(), size)
+            );
+        }
+    }
+
+    #[test]
+    fn names_targets_after_labels() {
+        assert_eq!(
+            disassemble([0xCD, 0x50, 0x01], 0x0100, labels).0,
+            "call Start"
+        );
+        assert_eq!(
+            disassemble([0xC2, 0x00, 0x40], 0x0100, labels).0,
+            "jp nz, BankedCode"
+        );
+        assert_eq!(
+            disassemble([0xFA, 0x00, 0xC0], 0x0100, labels).0,
+            "ld a, [wBuffer]"
+        );
+        assert_eq!(
+            disassemble([0xE0, 0x80, 0x00], 0x0100, labels).0,
+            "ldh [hCounter], a"
+        );
+        // JR targets are relative to the next instruction
+        assert_eq!(
+            disassemble([0x18, 0x4E, 0x00], 0x0100, labels).0,
+            "jr Start"
+        );
+        assert_eq!(
+            disassemble([0x20, 0xFE, 0x00], 0x0150, labels).0,
+            "jr nz, Start"
+        );
+        assert_eq!(
+            disassemble([0xC3, 0x51, 0x01], 0x0100, labels).0,
+            "jp $0151"
+        );
+    }
+}
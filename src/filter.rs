@@ -0,0 +1,307 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Post-processing applied to the frame on the CPU before it is shown, in the order given.
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    /// Nearest-neighbour scaling to twice the size
+    Nearest2x,
+    /// Nearest-neighbour scaling to three times the size
+    Nearest3x,
+    /// Nearest-neighbour scaling to four times the size
+    Nearest4x,
+    /// Scale2x pixel art scaling, smoothing diagonal edges
+    Scale2x,
+    /// Scale3x pixel art scaling, smoothing diagonal edges
+    Scale3x,
+    /// Darkened gaps between the LCD dots, scaling the frame to three times the size first if it
+    /// wasn't scaled yet
+    LcdGrid,
+    /// Blending with the previous frames, like the slow response of the DMG LCD
+    Ghosting,
+}
+
+/// Brightness kept in the gaps of the LCD grid, out of 256.
+const GRID_BRIGHTNESS: u32 = 160;
+/// Weight of the previous frames when ghosting, out of 256.
+const GHOSTING_WEIGHT: u32 = 112;
+
+/// RGBA frame the filters work on.
+#[derive(Clone, Default)]
+pub struct Buffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Buffer {
+    pub fn from_rgba(rgba: &[u8], width: usize) -> Buffer {
+        let pixels: Vec<[u8; 4]> = rgba
+            .chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect();
+        Buffer {
+            width,
+            height: pixels.len() / width,
+            pixels,
+        }
+    }
+
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels.concat()
+    }
+
+    /// Pixel at the specified coordinates, repeating the border pixels outside the frame.
+    fn get(&self, x: isize, y: isize) -> [u8; 4] {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// Builds a frame scaled by an integer factor, from the block of pixels every source pixel
+    /// turns into.
+    fn scale(&self, factor: usize, block: impl Fn(isize, isize, &mut [[u8; 4]])) -> Buffer {
+        let width = self.width * factor;
+        let mut pixels = vec![[0; 4]; width * self.height * factor];
+        let mut cells = vec![[0; 4]; factor * factor];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                block(x as isize, y as isize, &mut cells);
+                for (row, cells) in cells.chunks_exact(factor).enumerate() {
+                    let start = (y * factor + row) * width + x * factor;
+                    pixels[start..start + factor].copy_from_slice(cells);
+                }
+            }
+        }
+
+        Buffer {
+            width,
+            height: self.height * factor,
+            pixels,
+        }
+    }
+
+    fn nearest(&self, factor: usize) -> Buffer {
+        self.scale(factor, |x, y, cells| cells.fill(self.get(x, y)))
+    }
+
+    fn scale2x(&self) -> Buffer {
+        self.scale(2, |x, y, cells| {
+            let (a, b, c, d) = (
+                self.get(x, y - 1),
+                self.get(x + 1, y),
+                self.get(x - 1, y),
+                self.get(x, y + 1),
+            );
+            let p = self.get(x, y);
+            cells.fill(p);
+            if c == a && c != d && a != b {
+                cells[0] = a;
+            }
+            if a == b && a != c && b != d {
+                cells[1] = b;
+            }
+            if d == c && d != b && c != a {
+                cells[2] = c;
+            }
+            if b == d && b != a && d != c {
+                cells[3] = d;
+            }
+        })
+    }
+
+    fn scale3x(&self) -> Buffer {
+        self.scale(3, |x, y, cells| {
+            #[rustfmt::skip]
+            let [a, b, c, d, e, f, g, h, i] = [
+                (-1, -1), (0, -1), (1, -1),
+                (-1, 0), (0, 0), (1, 0),
+                (-1, 1), (0, 1), (1, 1),
+            ]
+            .map(|(dx, dy)| self.get(x + dx, y + dy));
+
+            cells.fill(e);
+            if b != h && d != f {
+                let pick = |condition: bool, color: [u8; 4]| if condition { color } else { e };
+                cells[0] = pick(d == b, d);
+                cells[1] = pick((d == b && e != c) || (b == f && e != a), b);
+                cells[2] = pick(b == f, f);
+                cells[3] = pick((d == b && e != g) || (d == h && e != a), d);
+                cells[5] = pick((b == f && e != i) || (h == f && e != c), f);
+                cells[6] = pick(d == h, d);
+                cells[7] = pick((d == h && e != i) || (h == f && e != g), h);
+                cells[8] = pick(h == f, f);
+            }
+        })
+    }
+
+    /// Darkens the last row and column of every dot, `scale` pixels wide.
+    fn lcd_grid(&mut self, scale: usize) {
+        for (index, pixel) in self.pixels.iter_mut().enumerate() {
+            let (x, y) = (index % self.width, index / self.width);
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                for channel in &mut pixel[..3] {
+                    *channel = (*channel as u32 * GRID_BRIGHTNESS / 256) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Filters applied to every frame, keeping the previous frame for ghosting.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+    /// Output of the ghosting filter for the last frame.
+    previous: Option<Buffer>,
+}
+
+impl FilterChain {
+    pub fn new(filters: Vec<Filter>) -> FilterChain {
+        FilterChain {
+            filters,
+            previous: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn apply(&mut self, frame: Buffer) -> Buffer {
+        let source_width = frame.width;
+        let mut frame = frame;
+
+        for filter in self.filters.clone() {
+            frame = match filter {
+                Filter::Nearest2x => frame.nearest(2),
+                Filter::Nearest3x => frame.nearest(3),
+                Filter::Nearest4x => frame.nearest(4),
+                Filter::Scale2x => frame.scale2x(),
+                Filter::Scale3x => frame.scale3x(),
+                Filter::LcdGrid => {
+                    if frame.width == source_width {
+                        frame = frame.nearest(3);
+                    }
+                    frame.lcd_grid(frame.width / source_width);
+                    frame
+                }
+                Filter::Ghosting => self.ghosting(frame),
+            };
+        }
+        frame
+    }
+
+    fn ghosting(&mut self, mut frame: Buffer) -> Buffer {
+        if let Some(previous) = self
+            .previous
+            .as_ref()
+            .filter(|previous| previous.pixels.len() == frame.pixels.len())
+        {
+            for (pixel, previous) in frame.pixels.iter_mut().zip(&previous.pixels) {
+                for channel in 0..3 {
+                    let blended = pixel[channel] as u32 * (256 - GHOSTING_WEIGHT)
+                        + previous[channel] as u32 * GHOSTING_WEIGHT;
+                    pixel[channel] = (blended / 256) as u8;
+                }
+            }
+        }
+        self.previous = Some(frame.clone());
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+    const B: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+    fn buffer(width: usize, pixels: &[[u8; 4]]) -> Buffer {
+        Buffer::from_rgba(&pixels.concat(), width)
+    }
+
+    fn apply(filters: &[Filter], frame: &Buffer) -> Buffer {
+        FilterChain::new(filters.to_vec()).apply(frame.clone())
+    }
+
+    #[test]
+    fn nearest_repeats_every_pixel() {
+        let frame = apply(&[Filter::Nearest2x], &buffer(2, &[W, B]));
+        assert_eq!((frame.width, frame.height), (4, 2));
+        assert_eq!(frame.pixels, [W, W, B, B, W, W, B, B]);
+    }
+
+    #[test]
+    fn scale2x_smooths_corner() {
+        let frame = apply(&[Filter::Scale2x], &buffer(2, &[W, B, B, B]));
+        assert_eq!((frame.width, frame.height), (4, 4));
+        #[rustfmt::skip]
+        assert_eq!(frame.pixels, [
+            W, W, B, B,
+            W, B, B, B,
+            B, B, B, B,
+            B, B, B, B,
+        ]);
+    }
+
+    #[test]
+    fn scale2x_keeps_edges_of_flat_areas() {
+        // The border pixels are repeated outside the frame, so they don't count as edges
+        let frame = apply(&[Filter::Scale2x], &buffer(2, &[W, W, B, B]));
+        #[rustfmt::skip]
+        assert_eq!(frame.pixels, [
+            W, W, W, W,
+            W, W, W, W,
+            B, B, B, B,
+            B, B, B, B,
+        ]);
+    }
+
+    #[test]
+    fn scale3x_smooths_corner() {
+        let frame = apply(&[Filter::Scale3x], &buffer(2, &[W, B, B, B]));
+        assert_eq!((frame.width, frame.height), (6, 6));
+        #[rustfmt::skip]
+        assert_eq!(frame.pixels, [
+            W, W, W, B, B, B,
+            W, W, B, B, B, B,
+            W, B, B, B, B, B,
+            B, B, B, B, B, B,
+            B, B, B, B, B, B,
+            B, B, B, B, B, B,
+        ]);
+    }
+
+    #[test]
+    fn lcd_grid_darkens_last_row_and_column_of_each_dot() {
+        let dark = [159, 159, 159, 0xFF];
+
+        let frame = apply(&[Filter::LcdGrid], &buffer(1, &[W]));
+        assert_eq!((frame.width, frame.height), (3, 3));
+        #[rustfmt::skip]
+        assert_eq!(frame.pixels, [
+            W, W, dark,
+            W, W, dark,
+            dark, dark, dark,
+        ]);
+
+        // An already scaled frame keeps its size
+        let frame = apply(&[Filter::Nearest2x, Filter::LcdGrid], &buffer(1, &[W]));
+        assert_eq!(frame.pixels, [W, dark, dark, dark]);
+    }
+
+    #[test]
+    fn ghosting_blends_with_previous_frame() {
+        let mut chain = FilterChain::new(vec![Filter::Ghosting]);
+        assert_eq!(chain.apply(buffer(1, &[W])).pixels, [W]);
+        assert_eq!(chain.apply(buffer(1, &[B])).pixels, [[111, 111, 111, 0xFF]]);
+        assert_eq!(chain.apply(buffer(1, &[B])).pixels, [[48, 48, 48, 0xFF]]);
+
+        // A frame of another size starts over
+        assert_eq!(chain.apply(buffer(2, &[B, B])).pixels, [B, B]);
+    }
+}
@@ -17,9 +17,20 @@ use crate::instruction::Operand::{OpByte, OpHL, OpRegister};
 use crate::instruction::{Command, Operand};
 use crate::interrupt::InterruptId;
 use crate::interrupt::InterruptId::{Input, Serial, Stat, Timing, VBlank};
+use crate::trace::{TraceTrigger, Tracer};
+use crate::disassembler;
+use crate::profiler::Profiler;
+use crate::logger::Logger;
+use crate::boot::HleBoot;
+use crate::recorder::RecordFormat;
+
+/// Layout of the save states, to be bumped whenever a serialized field changes.
+pub const SAVE_STATE_VERSION: u32 = 2;
 
 #[derive(Serialize, Deserialize)]
 pub struct Gameboy {
+    /// Save state layout, serialized first so that it can be checked on its own.
+    pub version: u32,
     #[serde(skip)]
     pub pin: Option<(u64, Instant)>,
     pub reg: Register,
@@ -28,33 +39,146 @@ pub struct Gameboy {
     halt_bug: bool,
     pub mmu: MemoryManagementUnit,
     pub halted: bool,
+    /// STOP mode, the CPU and LCD are stopped until a selected joypad line goes low.
+    pub stopped: bool,
+    /// Set after executing an illegal opcode, the CPU stays frozen while the rest keeps running.
+    pub locked: bool,
     counter: usize,
+    /// Built-in boot sequence, running while no boot ROM is used.
+    pub boot: Option<HleBoot>,
+    #[serde(skip)]
+    pub tracer: Option<Tracer>,
+    #[serde(skip)]
+    pub profiler: Option<Profiler>,
+    /// Conditions pausing the emulation before the instruction at PC runs.
+    #[serde(skip)]
+    pub breakpoints: Vec<TraceTrigger>,
+    /// Set once a breakpoint is reached, until the frame loop has paused.
+    #[serde(skip)]
+    pub break_hit: bool,
+    /// PC of the last breakpoint reached, which doesn't stop the first instruction after resuming.
+    #[serde(skip)]
+    break_pc: Option<u16>,
+    /// Frame after which the VRAM views are exported to PNG files.
+    #[serde(skip)]
+    pub vram_export: Option<usize>,
+    /// Integer factor screenshots are scaled up by.
+    #[serde(skip)]
+    pub screenshot_scale: u32,
+    /// File format of the recordings started with the record hotkey.
+    #[serde(skip)]
+    pub record_format: RecordFormat,
 }
 
 impl Gameboy {
     pub fn new(mem: MemoryManagementUnit) -> Self {
         Self {
+            version: SAVE_STATE_VERSION,
             pin: Some((0, Instant::now())),
             halt_bug: false,
-            reg: Register::new(mem.boot_rom.is_some()),
+            reg: Register::new(mem.boot_rom.is_some(), mem.model),
             mmu: mem,
             ei_counter: -1,
             ime: false,
             halted: false,
+            stopped: false,
+            locked: false,
             counter: 0,
+            boot: None,
+            tracer: None,
+            profiler: None,
+            breakpoints: vec![],
+            break_hit: false,
+            break_pc: None,
+            vram_export: None,
+            screenshot_scale: 1,
+            record_format: RecordFormat::default(),
         }
     }
 
     pub fn init(&mut self) {
         self.mmu.apu.init();
     }
+
+    /// Plays the boot animation before starting the cartridge, as no boot ROM is used.
+    pub fn hle_boot(&mut self) {
+        self.boot = Some(HleBoot::new(&mut self.mmu));
+    }
 }
 
 impl Gameboy {
+    /// Whether the instruction at PC hits a breakpoint, logging the registers and the upcoming
+    /// instructions if so. Frame breakpoints only stop once.
+    fn breakpoint_reached(&mut self) -> bool {
+        let pc = self.reg.pc.value();
+        if self.breakpoints.is_empty() || self.break_pc.take() == Some(pc) {
+            return false;
+        }
+
+        let Some(index) = self.breakpoints.iter().position(|b| b.matches(pc, &self.mmu)) else {
+            return false;
+        };
+        if let TraceTrigger::Frame(_) = self.breakpoints[index] {
+            self.breakpoints.remove(index);
+        }
+        self.break_pc = Some(pc);
+
+        Logger::info(format!(
+            "Breakpoint at {}\nAF:{:02X}{:02X} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X}\n{}",
+            self.mmu.describe_address(pc),
+            self.reg[A].value,
+            self.reg.flags.value(),
+            self.reg[B].value,
+            self.reg[C].value,
+            self.reg[D].value,
+            self.reg[E].value,
+            self.reg[H].value,
+            self.reg[L].value,
+            self.reg.sp.value(),
+            disassembler::listing(&self.mmu, pc, 5)
+        ));
+        true
+    }
+
     #[deny(unreachable_patterns)]
     pub fn cycle(&mut self) -> u8 {
+        if let Some(boot) = self.boot.as_mut() {
+            self.mmu.cycle(4);
+            if boot.machine_cycle(&mut self.mmu) {
+                self.boot = None;
+                self.reg = Register::new(false, self.mmu.model);
+                self.mmu.finish_boot();
+            }
+            return 1;
+        }
+
+        if self.mmu.stall_cycles > 0 {
+            self.mmu.stall_cycles -= 1;
+            self.machine_cycle();
+            return 1;
+        }
+
+        if self.stopped {
+            self.stopped = !self.mmu.stopped_cycle();
+            return 1;
+        }
+
+        if self.locked {
+            self.machine_cycle();
+            return 1;
+        }
+
         let interrupt_cycles = if self.handle_interrupts() { 5 } else { 0 };
 
+        if let Some(profiler) = self.profiler.as_mut() {
+            if self.halted {
+                profiler.idle(1);
+            }
+            if interrupt_cycles != 0 {
+                profiler.interrupt(self.reg.pc.value(), interrupt_cycles);
+            }
+        }
+
         if self.halted {
             self.halted = interrupt_cycles == 0;
             if self.halted
@@ -71,13 +195,34 @@ impl Gameboy {
             return interrupt_cycles;
         }
 
+        if self.breakpoint_reached() {
+            self.break_hit = true;
+            return 0;
+        }
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&self.reg, &self.mmu);
+        }
+
+        let pc = self.reg.pc.value();
+        let sp = self.reg.sp.value();
+        let bank = self.mmu.bank(pc);
+
         let instruction =
             Fetcher::fetch(self.halt_bug, self.reg.pc.value(), &self.reg, &mut self.mmu);
         let (_, command) = (instruction.0, instruction.1);
 
         self.set_pc(self.reg.pc.value() + command.size() as u16, false);
 
-        self.execute_instruction(command)
+        let cycles = self.execute_instruction(command);
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            let target = self.reg.pc.value();
+            let target = (self.mmu.bank(target), target);
+            profiler.instruction((bank, pc), command, cycles, sp, self.reg.sp.value(), target);
+        }
+
+        cycles
     }
 
     fn execute_instruction(&mut self, command: Command) -> u8 {
@@ -125,6 +270,7 @@ impl Gameboy {
     fn trigger_interrupt(&mut self, interrupt_id: InterruptId) -> bool {
         if self.mmu.interrupt_handler.triggered(interrupt_id) {
             self.machine_cycle();
+            self.mmu.corrupt_oam(self.reg.sp);
             self.machine_cycle();
             self.ime = false;
             self.mmu.interrupt_handler.unset(interrupt_id);
@@ -140,6 +286,12 @@ impl Gameboy {
         }
     }
 
+    /// Operands the decoder never produces for the instruction.
+    fn invalid_operand(&self, command: Command) -> ! {
+        let pc = self.reg.pc.value().wrapping_sub(command.size() as u16);
+        panic!("Invalid operand for {command:?} at {}", self.mmu.describe_address(pc))
+    }
+
     fn handle_command(&mut self, command: Command) -> u8 {
         let hl = self.reg.hl();
         let mut branch_taken = true;
@@ -147,6 +299,10 @@ impl Gameboy {
         match command {
             JrCcI8(cc, _) | JpCcU16(cc, _) | RetCc(cc) | CallCcU16(cc, _) => {
                 if self.reg.cc_flag(cc) {
+                    if let CallCcU16(..) = command {
+                        // The stack pointer is decremented while the jump is taken
+                        self.mmu.corrupt_oam(self.reg.sp);
+                    }
                     self.machine_cycle();
                 }
             }
@@ -311,7 +467,7 @@ impl Gameboy {
                 match op {
                     OpRegister(id) => self[id].value = value,
                     OpHL => self.mmu.write(hl, value),
-                    _ => panic!(),
+                    _ => self.invalid_operand(command),
                 };
                 self.reg.set_flags(z, false, false, carry);
             }
@@ -331,7 +487,7 @@ impl Gameboy {
                 match op {
                     OpHL => self.mmu.write(hl, value),
                     OpRegister(id) => self[id].value = value,
-                    _ => panic!(),
+                    _ => self.invalid_operand(command),
                 };
 
                 self.reg.set_flags(value == 0, false, false, carry);
@@ -393,20 +549,14 @@ impl Gameboy {
             LdhHlU8(n) => self.mmu.write(hl, n),
             LdhAC => self[A].value = self.mmu.read(self[C]),
             LdHldA => {
-                /*
-                TODO
-                 Figure out if OAM corruption bug happens,
-                 or if it gets ignored due to the Write + IncDec
-                 */
+                // The IDU access happens on the same M-cycle as the write, which already corrupts
+                // OAM the way a plain write does
                 self.set_word_register(hl.value().wrapping_sub(1), self.reg.hl());
                 self.mmu.write(hl, self[A]);
             }
             LdHliA => {
-                /*
-                TODO
-                 Figure out if OAM corruption bug happens,
-                 or if it gets ignored due to the Write + IncDec
-                 */
+                // The IDU access happens on the same M-cycle as the write, which already corrupts
+                // OAM the way a plain write does
                 self.mmu.write(hl, self[A]);
                 self.set_word_register(hl.value().wrapping_add(1), self.reg.hl());
             }
@@ -421,6 +571,7 @@ impl Gameboy {
                 self[A].value = self.mmu.read(hl);
             }
             CallU16(n) => {
+                self.mmu.corrupt_oam(self.reg.sp);
                 self.machine_cycle();
                 let [lo, hi] = self.reg.pc.value().to_le_bytes();
                 self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
@@ -439,12 +590,14 @@ impl Gameboy {
                     .set_flags(self.reg.flags.z, true, true, self.reg.flags.c);
             }
             Ret => {
+                self.mmu.corrupt_oam(self.reg.sp);
                 let lo = self.mmu.read(self.reg.sp);
                 let hi = self.mmu.read(self.reg.sp.value().wrapping_add(1));
                 self.set_pc(u16::from_le_bytes([lo, hi]), true);
                 self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
             }
             Reti => {
+                self.mmu.corrupt_oam(self.reg.sp);
                 let lo = self.mmu.read(self.reg.sp);
                 let hi = self.mmu.read(self.reg.sp.value().wrapping_add(1));
                 self.set_pc(u16::from_le_bytes([lo, hi]), true);
@@ -454,7 +607,9 @@ impl Gameboy {
             }
             Rst(rst_vec) => {
                 let [lo, hi] = self.reg.pc.value().to_le_bytes();
-                self.set_pc(rst_vec as u16, true);
+                self.set_pc(rst_vec as u16, false);
+                self.mmu.corrupt_oam(self.reg.sp);
+                self.machine_cycle();
                 self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
                 self.mmu.write(self.reg.sp, hi);
                 self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
@@ -483,7 +638,11 @@ impl Gameboy {
                 self.mmu.write(n, lo);
                 self.mmu.write(n + 1, hi);
             }
-            LdSpHl => self.set_word_register_with_micro_cycle(self.reg.hl().value(), self.reg.sp),
+            LdSpHl => {
+                // HL goes through the IDU on its way to SP
+                self.mmu.corrupt_oam(hl);
+                self.set_word_register_with_micro_cycle(hl.value(), self.reg.sp)
+            }
 
             PopR16(reg) => match reg {
                 WordRegister::Double(
@@ -503,9 +662,10 @@ impl Gameboy {
                     self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
                 }
 
-                _ => panic!(),
+                _ => self.invalid_operand(command),
             },
             PushAf => {
+                self.mmu.corrupt_oam(self.reg.sp);
                 self.machine_cycle();
                 self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
                 self.mmu.write(self.reg.sp, self[A]);
@@ -527,7 +687,7 @@ impl Gameboy {
                         let value = self[low].value;
                         self.mmu.write(self.reg.sp, value);
                     }
-                    _ => panic!(),
+                    _ => self.invalid_operand(command),
                 }
             }
             Ccf => {
@@ -568,6 +728,7 @@ impl Gameboy {
 
             RetCc(cc) => {
                 if self.reg.cc_flag(cc) {
+                    self.mmu.corrupt_oam(self.reg.sp);
                     let lo = self.mmu.read(self.reg.sp);
                     let hi = self.mmu.read(self.reg.sp.value().wrapping_add(1));
                     self.set_pc(u16::from_le_bytes([lo, hi]), false);
@@ -607,7 +768,36 @@ impl Gameboy {
                 }
             }
 
-            Stop => {}
+            Illegal(opcode) => {
+                let pc = self.reg.pc.value().wrapping_sub(1);
+                Logger::error(format!(
+                    "Illegal opcode {:#04X} at {}, CPU locked up",
+                    opcode,
+                    self.mmu.describe_address(pc)
+                ));
+                self.locked = true;
+            }
+
+            Stop if self.mmu.switch_speed() => {}
+            Stop => {
+                let button_held = self.mmu.joypad.selected_pressed();
+                let interrupt_pending = self.mmu.internal_read(IE_ADDRESS)
+                    & self.mmu.internal_read(IF_ADDRESS)
+                    & 0x1F
+                    != 0;
+
+                // Without a pending interrupt STOP behaves as a 2-byte opcode
+                if !interrupt_pending {
+                    self.set_pc(self.reg.pc.value().wrapping_add(1), false);
+                }
+
+                if !button_held {
+                    self.mmu.reset_divider();
+                    self.stopped = true;
+                } else if !interrupt_pending {
+                    self.halted = true;
+                }
+            }
         };
         command.cycles(branch_taken)
     }
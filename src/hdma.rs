@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mmu::MemoryArea;
+
+/// CGB VRAM DMA, copying blocks of 16 bytes from ROM or RAM into VRAM.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Hdma {
+    /// FF51-FF52: Source address, the lower 4 bits are ignored
+    source: u16,
+    /// FF53-FF54: Destination address in VRAM, the lower 4 bits are ignored
+    destination: u16,
+    /// Blocks left to copy, minus one. Reads as 0x7F once the transfer is done.
+    remaining: u8,
+    /// A general purpose transfer was requested, copying every block at once.
+    pub(crate) general: bool,
+    /// An HBlank transfer is copying one block at the start of every HBlank.
+    pub(crate) hblank: bool,
+}
+
+impl MemoryArea for Hdma {
+    fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            Hdma::HDMA5 if self.hblank => Some(self.remaining),
+            Hdma::HDMA5 => Some(0x80 | self.remaining),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> bool {
+        match address {
+            Hdma::HDMA1 => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            Hdma::HDMA2 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            Hdma::HDMA3 => {
+                self.destination = (self.destination & 0x00FF) | (((value & 0x1F) as u16) << 8)
+            }
+            Hdma::HDMA4 => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            // Clearing bit 7 while an HBlank transfer is running stops it
+            Hdma::HDMA5 if self.hblank && value & 0x80 == 0 => self.hblank = false,
+            Hdma::HDMA5 => {
+                self.remaining = value & 0x7F;
+                self.hblank = value & 0x80 != 0;
+                self.general = !self.hblank;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Hdma {
+    const HDMA1: usize = 0xFF51;
+    const HDMA2: usize = 0xFF52;
+    const HDMA3: usize = 0xFF53;
+    const HDMA4: usize = 0xFF54;
+    const HDMA5: usize = 0xFF55;
+
+    /// Size of the blocks copied at once.
+    pub const BLOCK: u16 = 0x10;
+
+    pub fn new() -> Self {
+        Self {
+            source: 0,
+            destination: 0,
+            remaining: 0x7F,
+            general: false,
+            hblank: false,
+        }
+    }
+
+    /// Advances to the next block, returning its source and VRAM destination addresses.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(Self::BLOCK);
+        self.destination = (self.destination + Self::BLOCK) & 0x1FF0;
+
+        let (remaining, done) = self.remaining.overflowing_sub(1);
+        self.remaining = remaining & 0x7F;
+        if done {
+            self.general = false;
+            self.hblank = false;
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MemoryManagementUnit;
+    use crate::model::Model;
+
+    fn cgb_memory() -> MemoryManagementUnit {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        let mut mmu = MemoryManagementUnit::for_rom(rom, Model::Cgb);
+        for offset in 0..0x20u16 {
+            mmu.write(0xC000 + offset, 0x40 + offset as u8);
+        }
+        mmu
+    }
+
+    fn start(mmu: &mut MemoryManagementUnit, destination: u16, hdma5: u8) {
+        mmu.write(0x51u8, 0xC0u8);
+        mmu.write(0x52u8, 0x00u8);
+        mmu.write(0x53u8, (destination >> 8) as u8);
+        mmu.write(0x54u8, destination as u8);
+        mmu.write(0x55u8, hdma5);
+    }
+
+    fn copied(mmu: &MemoryManagementUnit, index: usize) -> bool {
+        mmu.ppu.vram[index..index + 0x10]
+            .iter()
+            .enumerate()
+            .all(|(offset, &value)| value == 0x40 + (index & 0x10) as u8 + offset as u8)
+    }
+
+    #[test]
+    fn general_transfer_copies_every_block_into_selected_bank() {
+        let mut mmu = cgb_memory();
+        mmu.write(0x4Fu8, 0x01u8);
+        start(&mut mmu, 0x8800, 0x01);
+
+        assert!(copied(&mmu, 0x2800));
+        assert!(copied(&mmu, 0x2810));
+        assert_eq!(mmu.ppu.vram[0x2820], 0x00);
+        assert!(mmu.ppu.vram[0x0800..0x0820].iter().all(|&value| value == 0x00));
+        assert_eq!(mmu.read(0x55u8), 0xFF);
+    }
+
+    #[test]
+    fn hblank_transfer_copies_one_block_per_hblank() {
+        let mut mmu = cgb_memory();
+        start(&mut mmu, 0x8000, 0x81);
+        assert_eq!(mmu.read(0x55u8), 0x01);
+        assert!(!copied(&mmu, 0x0000));
+
+        while !copied(&mmu, 0x0000) {
+            mmu.cycle(4);
+        }
+        let line = mmu.ppu.ly;
+        assert!(!copied(&mmu, 0x0010));
+        assert_eq!(mmu.read(0x55u8), 0x00);
+
+        while !copied(&mmu, 0x0010) {
+            mmu.cycle(4);
+        }
+        assert_eq!(mmu.ppu.ly, line + 1);
+        assert_eq!(mmu.read(0x55u8), 0xFF);
+    }
+}
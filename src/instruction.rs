@@ -33,6 +33,8 @@ pub enum Command {
     DisableInterrupt,
     EnableInterrupt,
     Halt,
+    /// One of the undefined opcodes, which lock up the CPU until it's powered off
+    Illegal(u8),
     InchHl,
     IncR16(WordRegister),
     IncR8(RegisterId),
@@ -146,7 +148,7 @@ impl Command {
             },
 
             Daa | Cpl | Scf | Ccf | Halt | DisableInterrupt | EnableInterrupt | JpHl
-            | IncR8(..) | DecR8(..) | LdR8R8(..) | Nop | Stop => 1,
+            | IncR8(..) | DecR8(..) | LdR8R8(..) | Nop | Stop | Illegal(..) => 1,
 
             Sla(op) | Sra(op) | Srl(op) => match op {
                 OpRegister(_) => 2,
@@ -1,19 +1,14 @@
-use crate::joypad::SelectedButtons::{Action, Direction};
 use crate::mmu::MemoryArea;
 
 use serde::{Deserialize, Serialize};
 use winit::event::VirtualKeyCode;
 use winit::event::VirtualKeyCode::*;
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
-pub enum SelectedButtons {
-    Action = 0x10,
-    Direction = 0x20,
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub struct Joypad {
-    selected_buttons: SelectedButtons,
+    /// P14 and P15 as last written. A low P14 selects the direction buttons, a low P15 the
+    /// action buttons, and both can be selected at once.
+    lines: u8,
     action_buttons: u8,
     direction_buttons: u8,
     #[serde(skip)]
@@ -24,7 +19,7 @@ pub struct Joypad {
 
 impl MemoryArea for Joypad {
     fn read(&self, address: usize) -> Option<u8> {
-        let value = self.selected_buttons as u8 | self.buttons();
+        let value = self.lines | self.buttons();
         match address {
             0xFF00 => Some(value),
             _ => None,
@@ -33,13 +28,7 @@ impl MemoryArea for Joypad {
 
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
-            0xFF00 => {
-                self.selected_buttons = match value & 0x30 {
-                    0x20 | 0x30 => Direction,
-                    0x10 => Action,
-                    _ => self.selected_buttons,
-                }
-            }
+            0xFF00 => self.lines = value & 0x30,
             _ => return false,
         };
         true
@@ -51,7 +40,8 @@ impl Joypad {
         Self {
             action_buttons: 0x0F,
             direction_buttons: 0x0F,
-            selected_buttons: Action,
+            // The boot ROM leaves both lines low
+            lines: 0x00,
             held_direction: vec![],
             held_action: vec![],
         }
@@ -75,11 +65,24 @@ impl Joypad {
             & 0x0F
     }
 
+    /// Whether a button on the selected lines is pressed, pulling its input line low.
+    pub fn selected_pressed(&self) -> bool {
+        self.buttons() != 0x0F
+    }
+
+    /// Pressed action and direction buttons, with a set bit for every pressed button.
+    pub fn pressed(&self) -> (u8, u8) {
+        (!self.action_buttons & 0x0F, !self.direction_buttons & 0x0F)
+    }
+
     fn buttons(&self) -> u8 {
-        if self.selected_buttons == Action {
-            self.action_buttons
-        } else {
-            self.direction_buttons
+        let mut buttons = 0x0F;
+        if self.lines & 0x10 == 0 {
+            buttons &= self.direction_buttons;
+        }
+        if self.lines & 0x20 == 0 {
+            buttons &= self.action_buttons;
         }
+        buttons
     }
 }
@@ -0,0 +1,99 @@
+/// Layers drawn by the PPU, which can be hidden without the game noticing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Objects,
+}
+
+/// Display settings to hide layers or single OAM entries, for debugging and ripping artwork.
+/// The emulated LCDC is left untouched, hidden pixels are replaced by color 0 of the
+/// background or let the pixels behind them through.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+    /// One bit for every OAM entry that isn't drawn.
+    pub hidden_sprites: u64,
+    /// Outlines every OAM entry on top of the frame.
+    pub sprite_boxes: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            background: true,
+            window: true,
+            objects: true,
+            hidden_sprites: 0,
+            sprite_boxes: false,
+        }
+    }
+}
+
+impl Layers {
+    pub fn visible(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Background => self.background,
+            Layer::Window => self.window,
+            Layer::Objects => self.objects,
+        }
+    }
+
+    pub fn set_visible(&mut self, layer: Layer, visible: bool) {
+        match layer {
+            Layer::Background => self.background = visible,
+            Layer::Window => self.window = visible,
+            Layer::Objects => self.objects = visible,
+        }
+    }
+
+    pub fn toggle(&mut self, layer: Layer) {
+        self.set_visible(layer, !self.visible(layer));
+    }
+
+    pub fn sprite_visible(&self, index: u8) -> bool {
+        self.hidden_sprites & (1 << index) == 0
+    }
+
+    pub fn set_sprite_visible(&mut self, index: u8, visible: bool) {
+        if visible {
+            self.hidden_sprites &= !(1 << index);
+        } else {
+            self.hidden_sprites |= 1 << index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_layers_independently() {
+        let mut layers = Layers::default();
+        layers.toggle(Layer::Window);
+        assert!(layers.visible(Layer::Background));
+        assert!(!layers.visible(Layer::Window));
+        assert!(layers.visible(Layer::Objects));
+
+        layers.toggle(Layer::Window);
+        assert_eq!(layers, Layers::default());
+    }
+
+    #[test]
+    fn hides_single_oam_entries() {
+        let mut layers = Layers::default();
+        layers.set_sprite_visible(0, false);
+        layers.set_sprite_visible(39, false);
+        assert!(!layers.sprite_visible(0));
+        assert!(layers.sprite_visible(1));
+        assert!(!layers.sprite_visible(39));
+        assert_eq!(layers.hidden_sprites, 1 << 39 | 1);
+
+        layers.set_sprite_visible(39, true);
+        assert!(layers.sprite_visible(39));
+        assert_eq!(layers.hidden_sprites, 1);
+    }
+}
@@ -10,6 +10,16 @@ impl Logger {
         println!("{s}");
     }
 
+    pub fn warn<S: Into<String>>(s: S) {
+        let s: String = s.into();
+
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::warn_1(&s.into());
+
+        #[cfg(any(unix, windows))]
+        eprintln!("Warning: {s}");
+    }
+
     pub fn error<S: Into<String>>(s: S) {
         let s: String = s.into();
 
@@ -16,13 +16,15 @@ use {
     rand::distributions::Uniform,
     std::fs::{read, write, File},
     winit::event::Event,
-    winit::event::{WindowEvent::Focused},
+    winit::event::{WindowEvent, WindowEvent::Focused},
     std::thread,
+    crate::vram_viewer::ViewerWindow,
+    crate::recorder::Recorder,
 };
 
 use std::collections::HashMap;
 
-use gameboy::Gameboy;
+use gameboy::{Gameboy, SAVE_STATE_VERSION};
 
 use crate::mmu::MemoryManagementUnit;
 use instant::{Duration, Instant};
@@ -34,15 +36,24 @@ use std::sync::atomic::Ordering::Relaxed;
 
 use crate::cartridge::Cartridge;
 use crate::register::Register;
+use crate::model::Model;
+use crate::ppu::RenderMode;
+use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+use crate::palette::DmgPalette;
+use crate::layers::Layer;
+use crate::recorder::RecordFormat;
+use crate::filter::{Filter, FilterChain};
+use crate::settings::Settings;
 
 use clap::{Parser, ValueEnum};
+use serde::Deserialize;
 use cpal::traits::StreamTrait;
 
 use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
 use pixels::wgpu::PresentMode;
 
 use winit::dpi::LogicalSize;
-use winit::event::VirtualKeyCode::{Back, Down, Escape, Left, Return, Right, Up, C, F, S, Z, P, M};
+use winit::event::VirtualKeyCode::{Back, Down, Escape, Left, Return, Right, Up, C, F, S, Z, P, M, L, V, E, Key1, Key2, Key3, Key4, F12, R};
 use winit::event::{VirtualKeyCode};
 
 use winit::event_loop::EventLoop;
@@ -51,6 +62,11 @@ use winit::window::{Window, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 use crate::SaveFile::{Bin, Json};
 use crate::logger::Logger;
+use crate::symbols::Symbols;
+use crate::cdl::CodeDataLogger;
+use crate::profiler::Profiler;
+use crate::boot::HleBoot;
+use crate::trace::{TraceTrigger, Tracer};
 
 mod cartridge;
 mod gameboy;
@@ -74,6 +90,24 @@ mod apu;
 mod test;
 mod mbc5;
 mod logger;
+mod trace;
+mod symbols;
+mod disassembler;
+mod cdl;
+mod profiler;
+mod model;
+mod boot;
+mod boot_rom;
+mod hdma;
+mod colorization;
+mod sgb;
+mod palette;
+mod vram_viewer;
+mod layers;
+mod screenshot;
+mod recorder;
+mod filter;
+mod settings;
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
@@ -100,9 +134,81 @@ struct Args {
     #[clap(long)]
     boot_rom: Option<String>,
 
+    /// Hardware model to emulate, selects the state left behind by the boot ROM [default: cgb for cartridges supporting it, dmg otherwise]
+    #[clap(value_enum, long)]
+    model: Option<Model>,
+
+    /// Start the cartridge right away instead of playing the built-in DMG boot animation when no boot ROM is used, other models always do
+    #[clap(long, default_value = "false")]
+    skip_boot: bool,
+
+    /// Renderer drawing the pixels of each line, the scanline renderer is faster but ignores register writes made while a line is drawn
+    #[clap(value_enum, long, default_value_t = RenderMode::Fifo)]
+    renderer: RenderMode,
+
+    /// Colors for DMG games, either a preset (dmg, pocket, light, high-contrast or color-blind) or a palette file
+    #[clap(long, default_value = "dmg", value_parser = DmgPalette::parse)]
+    palette: DmgPalette,
+
+    /// Post-processing filters applied to every frame, as a comma separated list run in order [default: the filters of the settings file next to the ROM]
+    #[clap(value_enum, long, value_delimiter = ',')]
+    filter: Vec<Filter>,
+
+    /// Draw up to the specified number of sprites per line instead of the hardware limit of 10 to reduce flicker, 40 removes the limit [default: the sprite limit of the settings file next to the ROM]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(10..=40))]
+    sprite_limit: Option<u8>,
+
+    /// Scale screenshots up by the specified integer factor, 1 keeps the native resolution
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=16))]
+    screenshot_scale: u32,
+
+    /// File format of the recordings started with the record hotkey
+    #[clap(value_enum, long, default_value_t = RecordFormat::Avi)]
+    record_format: RecordFormat,
+
     /// Use specified file format for saves
     #[clap(value_enum, long, default_value_t = SaveFile::Bin)]
     format: SaveFile,
+
+    /// Write a Gameboy Doctor trace of every executed instruction to the specified file ("-" for stdout)
+    #[clap(long)]
+    trace: Option<String>,
+
+    /// Start tracing once the trigger is reached (pc:<hex address>, label:<symbol> or frame:<number>)
+    #[clap(long, requires = "trace")]
+    trace_start: Option<TraceTrigger>,
+
+    /// Stop tracing once the trigger is reached (pc:<hex address>, label:<symbol> or frame:<number>)
+    #[clap(long, requires = "trace")]
+    trace_stop: Option<TraceTrigger>,
+
+    /// Append the symbol at PC to each trace line, using the .sym file next to the ROM
+    #[clap(long, default_value = "false", requires = "trace")]
+    trace_labels: bool,
+
+    /// Pause and print the upcoming instructions once the trigger is reached (pc:<hex address>, label:<symbol> or frame:<number>), can be repeated
+    #[clap(long = "break", value_name = "TRIGGER")]
+    breakpoints: Vec<TraceTrigger>,
+
+    /// Record which ROM bytes run as code or are read as data to the specified code/data log file
+    #[clap(long)]
+    cdl: Option<String>,
+
+    /// Profile where cycles are spent and write a hot-spot report to the specified file on exit
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Export the VRAM tiles, tile maps and OAM table to PNG files next to the ROM once the specified frame is reached
+    #[clap(long)]
+    export_vram: Option<usize>,
+
+    /// Hide the specified OAM entries, as a comma separated list of indexes from 0 to 39
+    #[clap(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..40))]
+    hide_sprites: Vec<u8>,
+
+    /// Force LY to always read 0x90, as required to compare traces with Gameboy Doctor
+    #[clap(long, default_value = "false")]
+    trace_ly_stub: bool,
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug)]
@@ -127,6 +233,31 @@ impl SaveFile {
             Bin => bincode::serialize(gameboy).unwrap()
         }
     }
+
+    /// Restores a save state, refusing the ones made with another layout.
+    fn load(&self, data: &[u8]) -> Result<Gameboy, String> {
+        #[derive(Deserialize)]
+        struct Header {
+            version: Option<u32>,
+        }
+
+        // States made before the version was added start with the register count instead
+        let version = match self {
+            Json => serde_json::from_slice::<Header>(data).ok().and_then(|header| header.version),
+            Bin => bincode::deserialize::<u32>(data).ok()
+        };
+        if version != Some(SAVE_STATE_VERSION) {
+            return Err(format!(
+                "Unsupported save state version {}, this build loads version {SAVE_STATE_VERSION}",
+                version.map_or("unknown".to_string(), |version| version.to_string())
+            ));
+        }
+
+        match self {
+            Json => serde_json::from_slice(data).map_err(|e| format!("Invalid save state: {e}")),
+            Bin => bincode::deserialize(data).map_err(|e| format!("Invalid save state: {e}"))
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -231,7 +362,13 @@ async fn file_callback(pixels: Pixels, event_loop: EventLoop<()>, file: Option<w
     );
 
     let name = file.name().replace(".sav.bin", "").replace(".sav.json", "");
-    let gameboy = load_gameboy(pixels, file.name(), false, None, data);
+    let gameboy = match load_gameboy(pixels, file.name(), false, None, None, true, RenderMode::Fifo, data) {
+        Ok(gameboy) => gameboy,
+        Err(e) => {
+            Logger::error(e);
+            return;
+        }
+    };
 
     let doc = web_sys::window().unwrap().document().unwrap();
     doc.get_element_by_id("rom-selector")
@@ -246,7 +383,8 @@ async fn file_callback(pixels: Pixels, event_loop: EventLoop<()>, file: Option<w
         .unwrap()
         .set_attribute("style", "filter: brightness(1.5); transition: all 1.5s linear")
         .unwrap();
-    run_event_loop(event_loop, gameboy, true, false, name, SaveFile::Bin);
+    let palettes = DmgPalette::cycle(DmgPalette::default());
+    run_event_loop(event_loop, gameboy, true, false, name, SaveFile::Bin, palettes);
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -272,9 +410,55 @@ fn main_desktop() {
     let window = setup_window(rom_path.clone()).build(&event_loop).unwrap();
     let pixels = setup_pixels(&window);
     let rom = read(rom_path.clone()).expect("Unable to read ROM file");
-    let gameboy = load_gameboy(pixels, rom_path.clone(), args.cold_boot, args.boot_rom, rom);
+    let mut gameboy = load_gameboy(
+        pixels,
+        rom_path.clone(),
+        args.cold_boot,
+        args.boot_rom,
+        args.model,
+        !args.skip_boot,
+        args.renderer,
+        rom,
+    )
+    .unwrap_or_else(|e| {
+        Logger::error(e);
+        std::process::exit(1)
+    });
+
+    if let Some(trace) = args.trace {
+        let mut tracer = Tracer::to_path(&trace, args.trace_start, args.trace_stop)
+            .unwrap_or_else(|e| panic!("Unable to create trace file {trace}: {e}"));
+        tracer.labels = args.trace_labels;
+        gameboy.tracer = Some(tracer);
+    }
+    gameboy.mmu.ppu.ly_stub = args.trace_ly_stub;
+    gameboy.breakpoints = args.breakpoints;
+
+    if let Some(cdl) = args.cdl {
+        let rom_size = gameboy.mmu.rom().len();
+        gameboy.mmu.cdl = Some(CodeDataLogger::new(cdl.into(), rom_size));
+    }
+
+    if let Some(profile) = args.profile {
+        gameboy.profiler = Some(Profiler::new(profile.into()));
+    }
+
+    gameboy.vram_export = args.export_vram;
+    gameboy.screenshot_scale = args.screenshot_scale;
+    gameboy.record_format = args.record_format;
+    for index in args.hide_sprites {
+        gameboy.mmu.ppu.layers.set_sprite_visible(index, false);
+    }
+
+    let settings = Settings::for_rom(Path::new(&rom_path));
+    let filters = if args.filter.is_empty() { settings.filters } else { args.filter };
+    gameboy.mmu.renderer.filters = FilterChain::new(filters);
+    gameboy.mmu.ppu.sprite_limit = args.sprite_limit.or(settings.sprite_limit);
+
+    let palettes = DmgPalette::cycle(args.palette);
+    gameboy.mmu.ppu.palette = palettes[0].clone();
 
-    run_event_loop(event_loop, gameboy, !args.fast, false, rom_path, args.format);
+    run_event_loop(event_loop, gameboy, !args.fast, false, rom_path, args.format, palettes);
 }
 
 fn run_event_loop(
@@ -284,6 +468,7 @@ fn run_event_loop(
     mut muted: bool,
     rom_path: String,
     format: SaveFile,
+    palettes: Vec<DmgPalette>,
 ) {
     let mut input = WinitInputHelper::new();
 
@@ -298,6 +483,7 @@ fn run_event_loop(
     }
 
     let mut last_save = Instant::now();
+    let mut palette = 0;
 
     #[cfg(target_os = "macos")]
         let mut focus = (Instant::now(), true);
@@ -310,8 +496,26 @@ fn run_event_loop(
         let keymap = setup_virtual_pad();
 
     let mut previously_muted = false;
-    event_loop.run(move |event, _target, control_flow| {
+    #[cfg(any(unix, windows))]
+    let mut viewer: Option<ViewerWindow> = None;
+
+    event_loop.run(move |event, target, control_flow| {
         let gameboy = &mut gameboy;
+
+        #[cfg(any(unix, windows))]
+        if let Event::WindowEvent { window_id, event } = &event {
+            if let Some(window) = viewer.as_mut().filter(|window| window.id() == *window_id) {
+                match event {
+                    WindowEvent::CloseRequested => viewer = None,
+                    WindowEvent::Resized(size) => window.resize(size.width, size.height),
+                    _ => {}
+                }
+                return;
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = target;
+
         input.update(&event);
 
         if input.key_released(P) {
@@ -328,6 +532,17 @@ fn run_event_loop(
                 slowest_frame,
                 gameboy.mmu.renderer.slowest
             ));
+            if let Some(tracer) = gameboy.tracer.as_mut() {
+                tracer.flush();
+            }
+            if let Some(cdl) = &gameboy.mmu.cdl {
+                cdl.save();
+            }
+            if let Some(profiler) = gameboy.profiler.as_mut() {
+                profiler.save(gameboy.mmu.symbols.as_ref(), gameboy.mmu.frames);
+            }
+            #[cfg(any(unix, windows))]
+            stop_recording(gameboy);
             control_flow.set_exit();
         }
 
@@ -370,6 +585,26 @@ fn run_event_loop(
             muted = !muted;
         }
 
+        let layers = &mut gameboy.mmu.ppu.layers;
+        if input.key_released(Key1) {
+            layers.toggle(Layer::Background);
+        }
+        if input.key_released(Key2) {
+            layers.toggle(Layer::Window);
+        }
+        if input.key_released(Key3) {
+            layers.toggle(Layer::Objects);
+        }
+        if input.key_released(Key4) {
+            layers.sprite_boxes = !layers.sprite_boxes;
+        }
+
+        if input.key_released(L) {
+            palette = (palette + 1) % palettes.len();
+            gameboy.mmu.ppu.palette = palettes[palette].clone();
+            gameboy.mmu.ppu.recolor();
+        }
+
         if paused {
             return;
         }
@@ -393,6 +628,54 @@ fn run_event_loop(
             if slowest_frame < current_frame {
                 slowest_frame = current_frame;
             }
+
+            if input.key_released(V) {
+                viewer = match viewer.take() {
+                    Some(_) => None,
+                    None => ViewerWindow::open(target, &rom_path),
+                };
+            }
+            if let Some(window) = viewer.as_mut() {
+                window.draw(&gameboy.mmu.ppu);
+            }
+
+            if input.key_released(F12) {
+                match screenshot::save(&gameboy.mmu, &rom_path, gameboy.screenshot_scale) {
+                    Ok(path) => Logger::info(format!("Saved screenshot {path}")),
+                    Err(e) => Logger::error(format!("Unable to save screenshot: {e}")),
+                }
+            }
+
+            if input.key_released(R) {
+                if gameboy.mmu.recorder.is_some() {
+                    stop_recording(gameboy);
+                } else {
+                    match Recorder::start(&mut gameboy.mmu, &rom_path, gameboy.record_format) {
+                        Ok(recorder) => {
+                            Logger::info("Recording started.");
+                            gameboy.mmu.recorder = Some(recorder);
+                        }
+                        Err(e) => Logger::error(format!("Unable to start recording: {e}")),
+                    }
+                }
+            }
+
+            let export_due = gameboy.vram_export.is_some_and(|frame| gameboy.mmu.frames >= frame);
+            if input.key_released(E) || export_due {
+                gameboy.vram_export = None;
+                match vram_viewer::export(&gameboy.mmu.ppu, &rom_path) {
+                    Ok(()) => Logger::info(format!("Exported VRAM views next to {rom_path}")),
+                    Err(e) => Logger::error(format!("Unable to export VRAM views: {e}")),
+                }
+            }
+        }
+
+        if gameboy.break_hit {
+            gameboy.break_hit = false;
+            paused = true;
+            if let Some(stream) = &gameboy.mmu.apu.stream {
+                stream.pause().unwrap();
+            }
         }
 
         if let Some(stream) = &gameboy.mmu.apu.stream {
@@ -418,10 +701,14 @@ fn run_frame(gameboy: &mut Gameboy, sleep: bool, mute: &mut bool, input: Option<
         (1, Instant::now())
     };
 
-    while elapsed_cycles < CYCLES_PER_FRAME {
+    // Counted in half machine cycles, as double speed mode makes them twice as short
+    while elapsed_cycles < CYCLES_PER_FRAME * 2 {
         let previously_halted = gameboy.halted;
         let cycles = gameboy.cycle() as u16;
-        elapsed_cycles += cycles;
+        if gameboy.break_hit {
+            break;
+        }
+        elapsed_cycles += if gameboy.mmu.double_speed { cycles } else { cycles * 2 };
         let mem_cycles = cycles - gameboy.mmu.cycles;
         if mem_cycles != 0 && !previously_halted && !gameboy.halted {
             panic!("Cycle count after considering reads/writes: mem_cycles {} | cycles: {} | micro_ops: {}", mem_cycles, cycles, gameboy.mmu.cycles)
@@ -555,6 +842,16 @@ fn setup_virtual_pad() -> Arc<Mutex<HashMap<&'static str, AtomicBool>>> {
     keymap
 }
 
+#[cfg(any(unix, windows))]
+fn stop_recording(gameboy: &mut Gameboy) {
+    if let Some(recorder) = gameboy.mmu.recorder.take() {
+        match recorder.stop(&mut gameboy.mmu) {
+            Ok(path) => Logger::info(format!("Saved recording {path}")),
+            Err(e) => Logger::error(format!("Unable to save recording: {e}")),
+        }
+    }
+}
+
 fn save_state(rom_path: String, gameboy: &mut Gameboy, format: SaveFile) {
     Logger::info("Saving state.");
 
@@ -601,17 +898,26 @@ fn save_state(rom_path: String, gameboy: &mut Gameboy, format: SaveFile) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn load_gameboy(
-    pixels: Pixels,
+    mut pixels: Pixels,
     rom_path: String,
     cold_boot: bool,
     boot_rom: Option<String>,
-    mut data: Vec<u8>,
-) -> Gameboy {
+    model: Option<Model>,
+    hle_boot: bool,
+    render_mode: RenderMode,
+    data: Vec<u8>,
+) -> Result<Gameboy, String> {
     let mut gameboy = if rom_path.ends_with(".gb") || rom_path.ends_with(".gbc") {
         let cartridge = Cartridge::new(&data);
-        let mem = MemoryManagementUnit::new(data, cartridge, boot_rom, Path::new(&rom_path));
-        Gameboy::new(mem)
+        let model = model.unwrap_or_else(|| Model::for_cartridge(&cartridge));
+        let mem = MemoryManagementUnit::new(data, cartridge, boot_rom, model, Path::new(&rom_path), render_mode)?;
+        let mut gb = Gameboy::new(mem);
+        if hle_boot && gb.mmu.boot_rom.is_none() && HleBoot::supports(model) {
+            gb.hle_boot();
+        }
+        gb
     } else {
         let format = if rom_path.ends_with(".json") {
             Json
@@ -621,22 +927,24 @@ fn load_gameboy(
             panic!("Unexpected file format for ROM save file: {}", rom_path);
         };
 
-        let mut gb: Gameboy = match format {
-            Json => serde_json::from_slice(data.as_mut()).unwrap(),
-            Bin => bincode::deserialize(data.as_mut()).unwrap()
-        };
+        let mut gb = format.load(&data)?;
         gb.init();
+        gb.mmu.symbols = Symbols::for_rom(Path::new(&rom_path));
+        gb.mmu.ppu.render_mode = render_mode;
         gb
     };
 
     if cold_boot {
-        gameboy.reg = Register::new(gameboy.mmu.boot_rom.is_some())
+        gameboy.reg = Register::new(gameboy.mmu.boot_rom.is_some(), gameboy.mmu.model)
     }
 
+    if gameboy.mmu.sgb.is_some() {
+        pixels.resize_buffer(SGB_WIDTH as u32, SGB_HEIGHT as u32).unwrap();
+    }
     gameboy.mmu.renderer.set_pixels(pixels);
     gameboy.mmu.start();
 
-    gameboy
+    Ok(gameboy)
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -4,4 +4,16 @@ pub trait MemoryBankController: MemoryArea {
     fn start(&mut self) {}
 
     fn save(&mut self) {}
+
+    fn rom(&self) -> &[u8];
+
+    /// Offset into the ROM of the bank currently mapped at 4000-7FFF.
+    fn rom_offset(&self) -> usize {
+        0x4000
+    }
+
+    /// Offset into the ROM of the bank currently mapped at 0000-3FFF.
+    fn low_rom_offset(&self) -> usize {
+        0
+    }
 }
@@ -9,7 +9,11 @@ pub struct MBC0 {
     pub ram: Vec<u8>,
 }
 
-impl MemoryBankController for MBC0 {}
+impl MemoryBankController for MBC0 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
 
 impl MBC0 {
     pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Self {
@@ -24,18 +24,43 @@ impl MBC1 {
             cartridge,
             rom,
             ram: vec![0; 1024 * 1024 * 2],
+            rom_bank: 1,
             rom_offset: 0x4000,
             ..Default::default()
         }
     }
+
+    /// Maps the banks selected by the registers, wrapping around on carts smaller than the bank
+    /// number. RAM banking only applies in mode 1.
+    fn update_offsets(&mut self) {
+        self.rom_offset = self.rom_bank as usize * 0x4000 % self.rom.len();
+        self.ram_offset = if self.expansion_mode != 0 { self.ram_bank as usize * 0x2000 } else { 0 };
+    }
 }
 
-impl MemoryBankController for MBC1 {}
+impl MemoryBankController for MBC1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_offset(&self) -> usize {
+        self.rom_offset
+    }
+
+    /// Mode 1 also applies the upper two bank bits to 0000-3FFF, reaching the banks 0x20, 0x40
+    /// and 0x60 of carts of 1 MiB and more.
+    fn low_rom_offset(&self) -> usize {
+        if self.expansion_mode == 0 {
+            return 0;
+        }
+        (self.rom_bank & 0x60) as usize * 0x4000 % self.rom.len()
+    }
+}
 
 impl MemoryArea for MBC1 {
     fn read(&self, address: usize) -> Option<u8> {
         Some(match address {
-            0x0000..=0x3FFF => self.rom[address],
+            0x0000..=0x3FFF => self.rom[self.low_rom_offset() + address],
             0x4000..=0x7FFF => self.rom[self.rom_offset + (address & 0x3FFF)],
             0xA000..=0xBFFF if self.ram_enabled => self.ram[self.ram_offset + (address & 0x1FFF)],
             0xA000..=0xBFFF => 0xFF,
@@ -52,23 +77,24 @@ impl MemoryArea for MBC1 {
             0x2000..=0x3FFF => match self.cartridge.mbc {
                 1 | 2 | 3 => {
                     self.rom_bank = (self.rom_bank & 0x60) + max(1, value & 0x1F);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
+                    self.update_offsets();
                 }
                 _ => (),
             },
             0x4000..=0x5FFF => match self.cartridge.mbc {
-                1 | 2 | 3 if self.expansion_mode != 0 => {
-                    self.ram_bank = value & 3;
-                    self.ram_offset = self.ram_bank as usize * 0x2000;
-                }
+                // The same two bits select the RAM bank in mode 1
                 1 | 2 | 3 => {
+                    self.ram_bank = value & 3;
                     self.rom_bank = (self.rom_bank & 0x1F) + ((value & 3) << 5);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
+                    self.update_offsets();
                 }
                 _ => (),
             },
             0x6000..=0x7FFF => match self.cartridge.mbc {
-                2 | 3 => self.expansion_mode = value & 1,
+                1 | 2 | 3 => {
+                    self.expansion_mode = value & 1;
+                    self.update_offsets();
+                }
                 _ => (),
             },
             0xA000..=0xBFFF if self.ram_enabled => {
@@ -208,4 +208,12 @@ impl MemoryBankController for MBC3 {
     fn save(&mut self) {
         self.rtc.timestamp = SystemTime::now().duration_since(wasm_timer::UNIX_EPOCH).unwrap().as_secs();
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_offset(&self) -> usize {
+        self.rom_offset
+    }
 }
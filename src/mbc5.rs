@@ -30,7 +30,15 @@ impl MBC5 {
     }
 }
 
-impl MemoryBankController for MBC5 {}
+impl MemoryBankController for MBC5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_offset(&self) -> usize {
+        self.rom_offset
+    }
+}
 
 impl MemoryArea for MBC5 {
     fn read(&self, address: usize) -> Option<u8> {
@@ -3,7 +3,7 @@ use crate::interrupt::InterruptHandler;
 use crate::interrupt::InterruptId::{Input, Serial, Stat, Timing, VBlank};
 use crate::joypad::Joypad;
 use crate::mmu::OamCorruptionCause::{IncDec, Read, ReadWrite, Write};
-use crate::ppu::PixelProcessingUnit;
+use crate::ppu::{PixelProcessingUnit, RenderMode};
 use crate::timer::Timer;
 use std::any::{Any, TypeId};
 
@@ -16,7 +16,6 @@ use crate::mbc0::MBC0;
 use crate::mbc1::MBC1;
 
 use crate::renderer::Renderer;
-use std::fs::read;
 
 use crate::serial::LinkCable;
 
@@ -25,6 +24,15 @@ use crate::logger::Logger;
 use crate::mbc3::MBC3;
 use crate::mbc5::MBC5;
 use crate::mmu::Mbc::{Five, One, Three, Zero};
+use crate::model::Model;
+use crate::boot_rom;
+use crate::colorization::DmgPalettes;
+use crate::sgb::{self, Sgb, SGB_WIDTH};
+use crate::boot_rom::DMG_BOOT_ROM_SIZE;
+use crate::hdma::Hdma;
+use crate::symbols::Symbols;
+use crate::recorder::Recorder;
+use crate::cdl::{CodeDataLogger, RomAccess};
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub enum OamCorruptionCause {
@@ -39,6 +47,8 @@ pub struct MemoryManagementUnit {
     #[serde(skip)]
     pub(crate) renderer: Renderer,
     pub boot_rom: Option<Vec<u8>>,
+    /// Hardware revision, decides the state left behind by the boot ROM.
+    pub model: Model,
     mbc0: Option<MBC0>,
     mbc1: Option<MBC1>,
     mbc3: Option<MBC3>,
@@ -53,6 +63,30 @@ pub struct MemoryManagementUnit {
     pub cycles: u16,
     pub dma: u8,
     pub apu: AudioProcessingUnit,
+    /// Game Boy Color mode, when a CGB runs a cartridge with CGB support.
+    pub cgb: bool,
+    /// FF4D: KEY1, a speed switch was requested and happens on the next STOP.
+    speed_switch: bool,
+    /// The CPU and timer run at twice the speed of the PPU.
+    pub double_speed: bool,
+    /// FF70: SVBK, WRAM bank mapped at D000-DFFF.
+    wram_bank: u8,
+    hdma: Hdma,
+    /// Machine cycles the CPU is paused for, by a VRAM DMA or a speed switch.
+    pub(crate) stall_cycles: usize,
+    /// Super Game Boy functions, for cartridges supporting them on the SGB models.
+    pub sgb: Option<Sgb>,
+    /// Amount of frames rendered since the emulator started.
+    #[serde(skip)]
+    pub(crate) frames: usize,
+    /// Labels from the symbol file next to the ROM, if there is one.
+    #[serde(skip)]
+    pub(crate) symbols: Option<Symbols>,
+    #[serde(skip)]
+    pub(crate) cdl: Option<CodeDataLogger>,
+    /// Gameplay recording in progress, fed every frame rendered.
+    #[serde(skip)]
+    pub(crate) recorder: Option<Recorder>,
 }
 
 impl MemoryManagementUnit {
@@ -77,6 +111,103 @@ impl MemoryManagementUnit {
             mbc.start()
         }
     }
+
+    pub(crate) fn rom(&self) -> &[u8] {
+        if let Some(mbc) = &self.mbc0 {
+            mbc.rom()
+        } else if let Some(mbc) = &self.mbc1 {
+            mbc.rom()
+        } else if let Some(mbc) = &self.mbc3 {
+            mbc.rom()
+        } else if let Some(mbc) = &self.mbc5 {
+            mbc.rom()
+        } else {
+            &[]
+        }
+    }
+
+    fn rom_offset(&self) -> usize {
+        if let Some(mbc) = &self.mbc0 {
+            mbc.rom_offset()
+        } else if let Some(mbc) = &self.mbc1 {
+            mbc.rom_offset()
+        } else if let Some(mbc) = &self.mbc3 {
+            mbc.rom_offset()
+        } else if let Some(mbc) = &self.mbc5 {
+            mbc.rom_offset()
+        } else {
+            0x4000
+        }
+    }
+
+    fn low_rom_offset(&self) -> usize {
+        if let Some(mbc) = &self.mbc0 {
+            mbc.low_rom_offset()
+        } else if let Some(mbc) = &self.mbc1 {
+            mbc.low_rom_offset()
+        } else if let Some(mbc) = &self.mbc3 {
+            mbc.low_rom_offset()
+        } else if let Some(mbc) = &self.mbc5 {
+            mbc.low_rom_offset()
+        } else {
+            0
+        }
+    }
+
+    /// The bank currently mapped at the given address, numbered like the RGBDS symbol files:
+    /// the ROM bank, the VRAM bank and the WRAM bank selected by SVBK, which reads 1 on the DMG.
+    pub fn bank(&self, address: u16) -> u16 {
+        match address {
+            0x4000..=0x7FFF => (self.rom_offset() / 0x4000) as u16,
+            0x8000..=0x9FFF => self.ppu.vram_bank as u16,
+            0xD000..=0xDFFF => self.wram_bank.max(1) as u16,
+            _ => 0,
+        }
+    }
+
+    /// Offset into the ROM of the byte currently mapped at the given address.
+    pub(crate) fn rom_address(&self, address: usize) -> Option<usize> {
+        match address {
+            _ if self.boot_rom_mapped(address) => None,
+            0x0000..=0x3FFF => Some(self.low_rom_offset() + address),
+            0x4000..=0x7FFF => Some(self.rom_offset() + (address & 0x3FFF)),
+            _ => None,
+        }
+    }
+
+    /// Whether the boot ROM covers the cartridge at the given address. CGB boot ROMs leave a gap
+    /// for the cartridge header.
+    fn boot_rom_mapped(&self, address: usize) -> bool {
+        match &self.boot_rom {
+            Some(boot_rom) => {
+                address < DMG_BOOT_ROM_SIZE || (0x200..boot_rom.len()).contains(&address)
+            }
+            None => false,
+        }
+    }
+
+    fn log_rom_access(&mut self, address: usize, access: RomAccess) {
+        // Taken out while logging, as finding the ROM address borrows the whole MMU
+        if let Some(mut cdl) = self.cdl.take() {
+            if let Some(rom_address) = self.rom_address(address) {
+                cdl.log(rom_address, access);
+            }
+            self.cdl = Some(cdl);
+        }
+    }
+
+    /// Formats an address as `bank:address`, including the closest label when symbols are loaded.
+    pub fn describe_address(&self, address: u16) -> String {
+        Symbols::describe(self.symbols.as_ref(), self.bank(address), address)
+    }
+
+    /// Resolves a label to its address, if it's currently mapped in memory.
+    pub fn label_address(&self, label: &str) -> Option<u16> {
+        let (bank, address) = self.symbols.as_ref()?.address(label)?;
+        // The SRAM bank isn't tracked, and symbols without a bank are found in any bank
+        let mapped = self.bank(address) == bank || bank == 0 || (0xA000..0xC000).contains(&address);
+        mapped.then_some(address)
+    }
 }
 
 pub trait MemoryArea {
@@ -84,6 +215,74 @@ pub trait MemoryArea {
     fn write(&mut self, address: usize, value: u8) -> bool;
 }
 
+/// Bits of the I/O registers 0xFF00-0xFF7F that always read as 1, either because they are
+/// unused or because the register is write only. Unmapped registers read 0xFF.
+#[rustfmt::skip]
+const IO_READ_MASKS: [u8; 0x80] = [
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, // P1, SB, SC, -, DIV, TIMA, TMA, TAC
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0, // -, IF
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, // NR10-NR14, -, NR21, NR22
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF, // NR23, NR24, NR30-NR34, -
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, // NR41-NR44, NR50, NR51, NR52, -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Wave RAM
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP
+    0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, // OBP0, OBP1, WY, WX, -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Boot ROM unmap, -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // -
+];
+
+/// Bits of the I/O registers 0xFF00-0xFF7F that can be written. Read only bits keep their
+/// value, and writes to registers without any writable bit are dropped.
+#[rustfmt::skip]
+const IO_WRITE_MASKS: [u8; 0x80] = [
+    0x30, 0xFF, 0x83, 0x00, 0xFF, 0xFF, 0xFF, 0x07, // P1, SB, SC, -, DIV, TIMA, TMA, TAC
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F, // -, IF
+    0x7F, 0xFF, 0xFF, 0xFF, 0xC7, 0x00, 0xFF, 0xFF, // NR10-NR14, -, NR21, NR22
+    0xFF, 0xC7, 0x80, 0xFF, 0x60, 0xFF, 0xC7, 0x00, // NR23, NR24, NR30-NR34, -
+    0x3F, 0xFF, 0xFF, 0xC0, 0xFF, 0xFF, 0x80, 0x00, // NR41-NR44, NR50, NR51, NR52, -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Wave RAM
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Wave RAM
+    0xFF, 0x78, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP
+    0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, // OBP0, OBP1, WY, WX, -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Boot ROM unmap, -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // -
+];
+
+/// I/O registers only mapped in CGB mode.
+const CGB_REGISTERS: [usize; 9] = [
+    0xFF4D, 0xFF4F, 0xFF55, 0xFF68, 0xFF69, 0xFF6A, 0xFF6B, 0xFF6C, 0xFF70,
+];
+
+/// Memory buses of the DMG. OAM DMA takes over the bus it reads from, while HRAM and the I/O
+/// registers sit on the CPU internal bus and stay accessible.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Bus {
+    External,
+    Video,
+    Internal,
+}
+
+impl Bus {
+    fn of(address: usize) -> Self {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xFDFF => Bus::External,
+            0x8000..=0x9FFF => Bus::Video,
+            _ => Bus::Internal,
+        }
+    }
+}
+
 enum Mbc {
     Zero(MBC0),
     One(MBC1),
@@ -96,9 +295,24 @@ impl MemoryManagementUnit {
         rom: Vec<u8>,
         cartridge: Cartridge,
         boot_rom: Option<String>,
+        model: Model,
         rom_path: &Path,
-    ) -> MemoryManagementUnit {
-        let boot = boot_rom.map(read).map(|f| f.expect("Boot ROM not found"));
+        render_mode: RenderMode,
+    ) -> Result<MemoryManagementUnit, String> {
+        let boot = boot_rom.map(|path| boot_rom::load(&path)).transpose()?;
+        let model = match &boot {
+            Some((_, boot_model)) if *boot_model != model => {
+                Logger::info(format!(
+                    "Boot ROM belongs to {boot_model:?}, emulating it instead of {model:?}"
+                ));
+                *boot_model
+            }
+            _ => model,
+        };
+        let boot = boot.map(|(data, _)| data);
+        // The CGB boot ROM always starts in CGB mode, switching to DMG compatibility itself
+        let cgb = model.is_cgb() && (cartridge.cgb || boot.is_some());
+        let sgb = model.is_sgb() && cartridge.sgb;
 
         let (mbc0, mbc1, mbc3, mbc5) = match Self::load_mbc(cartridge, rom, rom_path) {
             Zero(mbc) => (Some(mbc), None, None, None),
@@ -107,26 +321,39 @@ impl MemoryManagementUnit {
             Five(mbc) => (None, None, None, Some(mbc))
         };
 
-        let mem = MemoryManagementUnit {
+        let mut mem = MemoryManagementUnit {
             renderer: Renderer::new(),
             high_ram: vec![0; 0x10000 - 0xFEA0],
             dma: 0xFF,
             joypad: Joypad::new(),
-            ppu: PixelProcessingUnit::new(),
+            ppu: PixelProcessingUnit::new(render_mode),
             interrupt_handler: InterruptHandler::new(),
-            timer: Timer::new(boot.is_some()),
-            work_ram: vec![0; 0xE000 - 0xC000],
+            timer: Timer::new(boot.is_some(), model),
+            work_ram: vec![0; 0x8000],
             cycles: 0,
             serial: LinkCable::new(),
             boot_rom: boot,
+            model,
             apu: AudioProcessingUnit::new(),
+            cgb,
+            speed_switch: false,
+            double_speed: false,
+            wram_bank: 1,
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            sgb: sgb.then(Sgb::new),
             mbc0,
             mbc1,
             mbc3,
-            mbc5
+            mbc5,
+            frames: 0,
+            symbols: Symbols::for_rom(rom_path),
+            cdl: None,
+            recorder: None,
         };
+        mem.ppu.cgb = cgb;
 
-        MemoryManagementUnit::init_memory(mem)
+        Ok(MemoryManagementUnit::init_memory(mem))
     }
 
     fn load_mbc(
@@ -164,13 +391,29 @@ impl MemoryManagementUnit {
     }
 
     pub fn read<T: 'static + Into<usize> + Copy>(&mut self, address: T) -> u8 {
+        self.logged_read(address, RomAccess::Data)
+    }
+
+    /// Reads the first byte of an instruction.
+    pub fn fetch_opcode(&mut self, address: u16) -> u8 {
+        self.logged_read(address, RomAccess::Opcode)
+    }
+
+    /// Reads the remaining bytes of an instruction.
+    pub fn fetch_operand(&mut self, address: u16) -> u8 {
+        self.logged_read(address, RomAccess::Operand)
+    }
+
+    fn logged_read<T: 'static + Into<usize> + Copy>(&mut self, address: T, access: RomAccess) -> u8 {
         let translated_address = if address.type_id() == TypeId::of::<u8>() {
             address.into() + 0xFF00
         } else {
             address.into()
         };
 
-        if self.boot_rom.is_some() && translated_address < 0x100 {
+        self.log_rom_access(translated_address, access);
+
+        if self.boot_rom_mapped(translated_address) {
             let value = self.boot_rom.as_ref().unwrap()[translated_address];
             self.cycle(4);
             return value;
@@ -187,7 +430,9 @@ impl MemoryManagementUnit {
             _ => None,
         };
 
-        let value = self.internal_read(translated_address);
+        let value = self
+            .dma_conflict(translated_address)
+            .unwrap_or_else(|| self.internal_read(translated_address));
 
         self.cycle(4);
         value
@@ -204,7 +449,8 @@ impl MemoryManagementUnit {
             address.into()
         };
 
-        if translated_address == 0xFF50 && self.boot_rom.is_some() && value.into() == 1 {
+        // The DMG boot ROM writes 0x01, the MGB one 0xFF and the CGB one 0x11
+        if translated_address == 0xFF50 && self.boot_rom.is_some() && value.into() & 0x01 != 0 {
             self.boot_rom = None;
             self.cycle(4);
             return;
@@ -220,18 +466,92 @@ impl MemoryManagementUnit {
             _ => None,
         };
 
-        self.internal_write(translated_address, value.into());
+        if self.dma_conflict(translated_address).is_none() {
+            self.internal_write(translated_address, value.into());
+        }
 
         self.cycle(4);
     }
 
     fn internal_ram_read(&self, address: usize) -> u8 {
         match address as u16 {
-            0xC000..=0xDFFF => self.work_ram[address - 0xC000],
-            0xE000..=0xFDFF => self.work_ram[address - 0x2000 - 0xC000],
-            0xFEA0..=0xFFFF => self.high_ram[address - 0xFEA0],
-            _ => panic!("Unhandled address for read: {}", address),
+            0xC000..=0xFDFF => self.work_ram[self.work_ram_index(address)],
+            // Unusable area, reads 0xFF while OAM is blocked and 0x00 otherwise
+            0xFEA0..=0xFEFF if self.ppu.dma_block_oam || self.ppu.oam_read_block => 0xFF,
+            0xFEA0..=0xFEFF => 0x00,
+            // Unmapped I/O registers
+            0xFF00..=0xFF7F => 0xFF,
+            0xFF80..=0xFFFF => self.high_ram[address - 0xFEA0],
+            _ => panic!("Unhandled address for read: {}", self.describe_address(address as u16)),
+        }
+    }
+
+    /// Index into `work_ram` of an address, including the echo RAM. Bank 0 is always mapped at
+    /// C000-CFFF, D000-DFFF maps the bank selected by SVBK, which is always 1 on DMG.
+    fn work_ram_index(&self, address: usize) -> usize {
+        let address = if address >= 0xE000 { address - 0x2000 } else { address };
+        match address {
+            0xC000..=0xCFFF => address - 0xC000,
+            _ => self.wram_bank.max(1) as usize * 0x1000 + address - 0xD000,
+        }
+    }
+
+    fn cgb_read(&self, address: usize) -> Option<u8> {
+        if !self.cgb {
+            return None;
+        }
+        match address {
+            0xFF4D => Some(0x7E | (self.double_speed as u8) << 7 | self.speed_switch as u8),
+            0xFF70 => Some(0xF8 | self.wram_bank),
+            _ => self.hdma.read(address),
+        }
+    }
+
+    fn cgb_write(&mut self, address: usize, value: u8) -> bool {
+        if !self.cgb {
+            return false;
+        }
+        match address {
+            0xFF4C if self.boot_rom.is_some() => {
+                if value & 0x04 != 0 {
+                    self.cgb = false;
+                    self.ppu.cgb = false;
+                    self.ppu.colorized = true;
+                }
+            }
+            0xFF4D => self.speed_switch = value & 0x01 != 0,
+            0xFF70 => self.wram_bank = value & 0x07,
+            _ if self.hdma.write(address, value) => {
+                while self.hdma.general {
+                    self.hdma_block();
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Copies the next VRAM DMA block, pausing the CPU while it's copied.
+    fn hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for offset in 0..Hdma::BLOCK {
+            let value = self.internal_read(source.wrapping_add(offset) as usize);
+            let index = self.ppu.vram_index((destination + offset) as usize);
+            self.ppu.vram[index] = value;
+        }
+        self.stall_cycles += if self.double_speed { 16 } else { 8 };
+    }
+
+    /// Switches the CPU speed if it was requested through KEY1, as STOP is being executed.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb || !self.speed_switch {
+            return false;
         }
+        self.speed_switch = false;
+        self.double_speed = !self.double_speed;
+        self.timer.reset_divider();
+        self.stall_cycles += 2050;
+        true
     }
 
     fn mbc_read(&self, translated_address: usize) -> Option<u8> {
@@ -255,6 +575,8 @@ impl MemoryManagementUnit {
             mbc.write(translated_address, value)
         } else if let Some(mbc) = &mut self.mbc3 {
             mbc.write(translated_address, value)
+        } else if let Some(mbc) = &mut self.mbc5 {
+            mbc.write(translated_address, value)
         } else {
             false
         }
@@ -262,37 +584,91 @@ impl MemoryManagementUnit {
 
     fn internal_ram_write(&mut self, address: usize, value: u8) {
         match address as u16 {
-            0xC000..=0xDFFF => self.work_ram[address - 0xC000] = value,
-            0xE000..=0xFDFF => self.work_ram[address - 0x2000 - 0xC000] = value,
-            0xFEA0..=0xFFFF => self.high_ram[address - 0xFEA0] = value,
-            _ => panic!("Unhandled address for write: {}", address),
+            0xC000..=0xFDFF => {
+                let index = self.work_ram_index(address);
+                self.work_ram[index] = value
+            }
+            0xFEA0..=0xFF7F => (),
+            0xFF80..=0xFFFF => self.high_ram[address - 0xFEA0] = value,
+            _ => panic!("Unhandled address for write: {}", self.describe_address(address as u16)),
         }
     }
 
     pub fn internal_read(&self, translated_address: usize) -> u8 {
-        self.mbc_read(translated_address)
+        let mask = match translated_address {
+            // These read their unused bits as 1 themselves
+            _ if self.cgb && CGB_REGISTERS.contains(&translated_address) => 0x00,
+            0xFF00..=0xFF7F => IO_READ_MASKS[translated_address - 0xFF00],
+            _ => 0x00,
+        };
+
+        mask | self
+            .mbc_read(translated_address)
             .or_else(|| self.ppu.read(translated_address))
             .or_else(|| self.interrupt_handler.read(translated_address))
             .or_else(|| self.timer.read(translated_address))
+            .or_else(|| self.sgb.as_ref().and_then(|sgb| sgb.read(translated_address)))
             .or_else(|| self.joypad.read(translated_address))
             .or_else(|| self.serial.read(translated_address))
             .or_else(|| self.apu.read(translated_address))
+            .or_else(|| self.cgb_read(translated_address))
             .unwrap_or_else(|| self.internal_ram_read(translated_address))
     }
 
-    fn internal_write(&mut self, translated_address: usize, value: u8) {
+    pub(crate) fn internal_write(&mut self, translated_address: usize, value: u8) {
+        let mask = match translated_address {
+            // The CGB registers, KEY0 and the HDMA sources handle their own bits
+            0xFF4C..=0xFF7F if self.cgb => 0xFF,
+            0xFF00..=0xFF7F => IO_WRITE_MASKS[translated_address - 0xFF00],
+            _ => 0xFF,
+        };
+        if mask == 0x00 {
+            return;
+        }
+        let value = value & mask;
+
         if !(self.mbc_write(translated_address, value)
             || self.ppu.write(translated_address, value)
             || self.interrupt_handler.write(translated_address, value)
             || self.timer.write(translated_address, value)
+            || self.sgb.as_mut().is_some_and(|sgb| sgb.write(translated_address, value))
             || self.joypad.write(translated_address, value)
             || self.serial.write(translated_address, value)
-            || self.apu.write(translated_address, value))
+            || self.apu.write(translated_address, value)
+            || self.cgb_write(translated_address, value))
         {
             self.internal_ram_write(translated_address, value);
         }
     }
 
+    /// Machine cycle spent in STOP mode. The system clock is stopped, so only the joypad lines
+    /// are sensed. Returns whether a selected button is pressed, waking the CPU up.
+    pub fn stopped_cycle(&mut self) -> bool {
+        self.cycles += 1;
+        self.joypad.machine_cycle();
+        self.joypad.selected_pressed()
+    }
+
+    pub fn reset_divider(&mut self) {
+        self.timer.reset_divider();
+    }
+
+    /// Leaves the timer with the DIV phase the boot ROM of the model ends on.
+    pub(crate) fn finish_boot(&mut self) {
+        self.timer = Timer::new(false, self.model);
+        self.colorize();
+    }
+
+    /// Colors a DMG cartridge on the CGB, with the palettes its boot ROM would pick.
+    fn colorize(&mut self) {
+        if !self.model.is_cgb() || self.cgb {
+            return;
+        }
+        let header: Vec<u8> = (0..0x150).map(|address| self.internal_read(address)).collect();
+        let (action, direction) = self.joypad.pressed();
+        self.ppu.colorize(DmgPalettes::for_cartridge(&header, action, direction));
+    }
+
     pub fn cycle(&mut self, ticks: usize) {
         self.cycles += 1;
         self.dma_transfer();
@@ -301,56 +677,100 @@ impl MemoryManagementUnit {
 
     const WIDTH: usize = 160;
 
+    /// Copies one byte per machine cycle, after an initial delay of 2 machine cycles.
     pub fn dma_transfer(&mut self) {
+        if self.ppu.dma_pending && self.ppu.ticks.wrapping_sub(self.ppu.dma_started) >= 8 {
+            self.ppu.dma_pending = false;
+            self.ppu.dma_running = true;
+            self.ppu.dma_index = 0;
+            self.ppu.dma_source = if self.ppu.dma >= 0xFE {
+                self.ppu.dma - 0x20
+            } else {
+                self.ppu.dma
+            } as usize
+                * 0x100;
+        }
+
         if !self.ppu.dma_running {
             return;
         }
-        let elapsed = self.ppu.ticks.wrapping_sub(self.ppu.dma_started);
-        if elapsed < 8 {
+
+        if self.ppu.dma_index == Self::WIDTH {
+            self.ppu.dma_block_oam = false;
+            self.ppu.dma_running = false;
             return;
         }
 
         self.ppu.dma_block_oam = true;
 
-        // 8 cycles delay + 160 machine cycles
-        if elapsed < 8 + Self::WIDTH * 4 {
-            return;
+        let address = self.ppu.dma_source + self.ppu.dma_index;
+        self.log_rom_access(address, RomAccess::Data);
+        self.ppu.oam[self.ppu.dma_index] = self.dma_read(address);
+        self.ppu.dma_index += 1;
+    }
+
+    fn dma_read(&self, address: usize) -> u8 {
+        match address {
+            0x8000..=0x9FFF => self.ppu.vram[self.ppu.vram_index(address)],
+            _ => self.internal_read(address),
         }
+    }
 
-        // Finish running
-        self.ppu.dma_block_oam = false;
-        self.ppu.dma_running = false;
+    /// The byte the DMA transfer reads in the current machine cycle, if the CPU would fight it
+    /// for the bus used to access the given address.
+    fn dma_conflict(&self, address: usize) -> Option<u8> {
+        if !self.ppu.dma_running || self.ppu.dma_index == Self::WIDTH {
+            return None;
+        }
 
-        // Copy memory
-        let start = if self.ppu.dma >= 0xFE {
-            self.ppu.dma - 0x20
-        } else {
-            self.ppu.dma
-        } as usize
-            * 0x100;
+        let dma_address = self.ppu.dma_source + self.ppu.dma_index;
+        (Bus::of(address) == Bus::of(dma_address) && Bus::of(address) != Bus::Internal)
+            .then(|| self.dma_read(dma_address))
+    }
 
-        for (index, address) in (start..start + Self::WIDTH).enumerate() {
-            self.ppu.oam[index] = match address {
-                0x8000..=0x9FFF => self.ppu.vram[address - 0x8000],
-                _ => self.internal_read(address),
-            };
+    fn render(&mut self) {
+        let boxes = if self.ppu.layers.sprite_boxes { self.ppu.sprite_boxes() } else { vec![] };
+        match self.sgb.as_mut() {
+            Some(sgb) => {
+                sgb.frame_done(&self.ppu);
+                self.renderer.render(&sgb.frame, SGB_WIDTH, &boxes, (sgb::SCREEN_X, sgb::SCREEN_Y));
+            }
+            None => self.renderer.render(&self.ppu.screen, crate::WIDTH, &boxes, (0, 0)),
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.frame(self);
         }
     }
 
     fn machine_cycle(&mut self, ticks: usize) {
-        match self.ppu.machine_cycle(ticks) {
+        let mode = self.ppu.stat & 0b11;
+        let ppu_ticks = if self.double_speed { ticks / 2 } else { ticks };
+        self.apu.cycle(ppu_ticks);
+
+        match self.ppu.machine_cycle(ppu_ticks) {
             (true, true) => {
-                self.renderer.render(&self.ppu.screen);
+                self.frames += 1;
+                self.render();
                 self.interrupt_handler.set(VBlank);
                 self.interrupt_handler.set(Stat);
             }
             (true, false) => {
-                self.renderer.render(&self.ppu.screen);
+                self.frames += 1;
+                self.render();
                 self.interrupt_handler.set(VBlank)
             }
             (false, true) => self.interrupt_handler.set(Stat),
             (false, false) => (),
         };
+        if self.ppu.take_blank_frame() {
+            self.frames += 1;
+            self.render();
+        }
+
+        // HBlank VRAM DMA copies a block as mode 3 ends
+        if self.hdma.hblank && mode == 3 && self.ppu.stat & 0b11 == 0 {
+            self.hdma_block();
+        }
 
         if self.timer.machine_cycle(ticks as u16) {
             self.interrupt_handler.set(Timing)
@@ -379,6 +799,7 @@ impl MemoryManagementUnit {
             0xFF05: 0x0,
             0xFF06: 0x0,
             0xFF07: 0x0,
+            0xFF0F: 0x1,
             0xFF10: 0x80,
             0xFF11: 0xBF,
             0xFF12: 0xF3,
@@ -386,15 +807,15 @@ impl MemoryManagementUnit {
             0xFF16: 0x3F,
             0xFF16: 0x3F,
             0xFF17: 0x0,
-            0xFF19: 0xBF,
+            0xFF19: 0x3F,
             0xFF1A: 0x7F,
             0xFF1B: 0xFF,
             0xFF1C: 0x9F,
-            0xFF1E: 0xFF,
+            0xFF1E: 0x3F,
             0xFF20: 0xFF,
             0xFF21: 0x0,
             0xFF22: 0x0,
-            0xFF23: 0xBF,
+            0xFF23: 0x3F,
             0xFF24: 0x77,
             0xFF25: 0xF3,
             0xFF26: 0xF1,
@@ -407,9 +828,23 @@ impl MemoryManagementUnit {
             0xFF49: 0xFF,
             0xFF4A: 0x0,
             0xFF4B: 0x0,
-            0xFF00: 0xFF,
+            0xFF00: 0x0,
         }
 
+        for &(address, value) in mem.model.io_registers() {
+            mem.internal_write(address, value);
+        }
+        mem.colorize();
+
         mem
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl MemoryManagementUnit {
+    /// Memory of a cartridge made of the given ROM, started without a boot ROM.
+    pub(crate) fn for_rom(rom: Vec<u8>, model: Model) -> Self {
+        let cartridge = Cartridge::new(&rom);
+        Self::new(rom, cartridge, None, model, Path::new("test.gb"), RenderMode::Fifo).unwrap()
+    }
+}
@@ -0,0 +1,80 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::Cartridge;
+
+/// Hardware revision being emulated. Each boot ROM leaves the system in a slightly different
+/// state when it hands control over to the cartridge.
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Model {
+    /// Original Game Boy with the early boot ROM revision
+    Dmg0,
+    /// Original Game Boy
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Game Boy Light
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Super Game Boy 2
+    Sgb2,
+    /// Game Boy Color
+    Cgb,
+    /// Game Boy Advance
+    Agb,
+}
+
+impl Model {
+    /// The CGB features are available to cartridges supporting them.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    /// The SGB functions are available to cartridges supporting them.
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    /// The model a cartridge is made for, CGB if it supports it.
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        if cartridge.cgb {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    /// A, F, B, C, D, E, H and L after the boot ROM.
+    pub fn registers(self) -> [u8; 8] {
+        match self {
+            Model::Dmg0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            Model::Dmg => [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Mgb => [0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Sgb2 => [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            Model::Agb => [0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        }
+    }
+
+    /// Internal 16 bit counter behind DIV after the boot ROM. Pan Docs doesn't list it for the
+    /// SGB and AGB, whose boot ROMs take as long as the SNES handshake or the palette selection.
+    /// The CGB value is the one for CGB cartridges.
+    pub fn divider(self) -> Option<u16> {
+        match self {
+            Model::Dmg0 => Some(0x18CC),
+            Model::Dmg | Model::Mgb => Some(0xABCC),
+            Model::Cgb => Some(0x1EA0),
+            Model::Sgb | Model::Sgb2 | Model::Agb => None,
+        }
+    }
+
+    /// I/O registers whose value after the boot ROM differs from the DMG. The registers of the
+    /// CGB only exist on that model and are set up when it is created.
+    pub fn io_registers(self) -> &'static [(usize, u8)] {
+        match self {
+            Model::Sgb | Model::Sgb2 => &[(0xFF26, 0xF0)],
+            _ => &[],
+        }
+    }
+}
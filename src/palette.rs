@@ -0,0 +1,188 @@
+use std::fs::read_to_string;
+
+use clap::ValueEnum;
+
+/// Colors of the four DMG shades as RGB, from the lightest to the darkest.
+pub type Ramp = [[u8; 3]; 4];
+
+/// Built-in palettes for DMG games.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PalettePreset {
+    /// Greenish screen of the original Game Boy
+    #[default]
+    Dmg,
+    /// Grey screen of the Game Boy Pocket
+    Pocket,
+    /// Blue-green backlight of the Game Boy Light
+    Light,
+    /// Evenly spaced greys from white to black
+    HighContrast,
+    /// Shades told apart by hue as well as brightness
+    ColorBlind,
+}
+
+impl PalettePreset {
+    pub fn palette(self) -> DmgPalette {
+        let ramp = match self {
+            PalettePreset::Dmg => [[0xE0, 0xF8, 0xD0], [0x88, 0xC0, 0x70], [0x27, 0x50, 0x46], [0x08, 0x18, 0x20]],
+            PalettePreset::Pocket => [[0xE0, 0xDB, 0xCD], [0xA8, 0x9F, 0x94], [0x70, 0x6B, 0x66], [0x2B, 0x2B, 0x26]],
+            PalettePreset::Light => [[0x00, 0xB5, 0x81], [0x00, 0x9A, 0x71], [0x00, 0x69, 0x4A], [0x00, 0x4F, 0x3B]],
+            PalettePreset::HighContrast => [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]],
+            PalettePreset::ColorBlind => [[0xFF, 0xFF, 0xFF], [0xE6, 0x9F, 0x00], [0x00, 0x72, 0xB2], [0x00, 0x00, 0x00]],
+        };
+        DmgPalette::uniform(ramp)
+    }
+}
+
+/// Colors DMG games are shown with, with a separate ramp for the background and each object
+/// palette.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DmgPalette {
+    pub bg: Ramp,
+    pub obj0: Ramp,
+    pub obj1: Ramp,
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        PalettePreset::default().palette()
+    }
+}
+
+impl DmgPalette {
+    fn uniform(ramp: Ramp) -> Self {
+        Self {
+            bg: ramp,
+            obj0: ramp,
+            obj1: ramp,
+        }
+    }
+
+    /// Ramp of the background, or of the object palette a pixel came from.
+    pub fn ramp(&self, object_palette: Option<u8>) -> &Ramp {
+        match object_palette {
+            None => &self.bg,
+            Some(0) => &self.obj0,
+            Some(_) => &self.obj1,
+        }
+    }
+
+    /// Parses a preset name or the path of a palette file.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match PalettePreset::from_str(value, true) {
+            Ok(preset) => Ok(preset.palette()),
+            Err(_) => Self::load(value),
+        }
+    }
+
+    /// Reads a palette file, with a line of four hex RGB colors for every ramp. A single line
+    /// is used by every layer, three lines set the BG, OBJ0 and OBJ1 ramps. Text after a # is
+    /// ignored.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = read_to_string(path).map_err(|e| format!("Unable to read palette {path}: {e}"))?;
+        Self::parse_ramps(&text).map_err(|e| format!("Invalid palette {path}: {e}"))
+    }
+
+    fn parse_ramps(text: &str) -> Result<Self, String> {
+        let ramps = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(Self::parse_ramp)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match ramps[..] {
+            [ramp] => Ok(Self::uniform(ramp)),
+            [bg, obj0, obj1] => Ok(Self { bg, obj0, obj1 }),
+            _ => Err(format!("expected 1 or 3 lines of colors, got {}", ramps.len())),
+        }
+    }
+
+    fn parse_ramp(line: &str) -> Result<Ramp, String> {
+        let colors = line
+            .split_whitespace()
+            .map(|color| match u32::from_str_radix(color, 16) {
+                // from_str_radix also takes a sign
+                Ok(rgb) if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+                }
+                _ => Err(format!("invalid color {color}")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        colors
+            .try_into()
+            .map_err(|colors: Vec<_>| format!("expected 4 colors per line, got {}", colors.len()))
+    }
+
+    /// Palettes the palette hotkey cycles through, starting with the selected one.
+    pub fn cycle(selected: DmgPalette) -> Vec<DmgPalette> {
+        let presets = PalettePreset::value_variants()
+            .iter()
+            .map(|preset| preset.palette())
+            .filter(|palette| *palette != selected);
+        let mut palettes: Vec<DmgPalette> = presets.collect();
+        palettes.insert(0, selected);
+        palettes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREYS: Ramp = [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]];
+
+    #[test]
+    fn parses_single_ramp_for_every_layer() {
+        let palette = DmgPalette::parse_ramps("FFFFFF aaaaaa 555555 000000\n").unwrap();
+        assert_eq!(palette, DmgPalette::uniform(GREYS));
+    }
+
+    #[test]
+    fn parses_three_ramps_and_skips_comments() {
+        let text = "# BG\n\
+            FFFFFF AAAAAA 555555 000000\n\
+            \n\
+            FF0000 AA0000 550000 000000 # OBJ0\n\
+            0000FF 0000AA 000055 000000\n";
+        let palette = DmgPalette::parse_ramps(text).unwrap();
+        assert_eq!(palette.bg, GREYS);
+        assert_eq!(palette.obj0[1], [0xAA, 0x00, 0x00]);
+        assert_eq!(palette.obj1[2], [0x00, 0x00, 0x55]);
+    }
+
+    #[test]
+    fn rejects_bad_ramps() {
+        let errors = [
+            ("", "expected 1 or 3 lines of colors, got 0"),
+            ("FFFFFF AAAAAA 555555 000000\nFFFFFF AAAAAA 555555 000000", "expected 1 or 3 lines of colors, got 2"),
+            ("FFFFFF AAAAAA 555555", "expected 4 colors per line, got 3"),
+            ("FFFFFF AAAAAA 555555 000000 000000", "expected 4 colors per line, got 5"),
+            ("FFFFFF AAAAAA 555555 00000G", "invalid color 00000G"),
+            ("FFFFFF AAAAAA 555555 FFF", "invalid color FFF"),
+            ("FFFFFF AAAAAA 555555 +FFFFF", "invalid color +FFFFF"),
+        ];
+        for (text, error) in errors {
+            assert_eq!(DmgPalette::parse_ramps(text), Err(error.to_string()), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn parses_presets_by_name() {
+        assert_eq!(DmgPalette::parse("high-contrast"), Ok(DmgPalette::uniform(GREYS)));
+        assert!(DmgPalette::parse("/nonexistent/palette.txt").is_err());
+    }
+
+    #[test]
+    fn cycle_starts_with_selected_palette() {
+        let custom = DmgPalette::parse_ramps("FF0000 AA0000 550000 000000").unwrap();
+        let palettes = DmgPalette::cycle(custom.clone());
+        assert_eq!(palettes[0], custom);
+        assert_eq!(palettes.len(), PalettePreset::value_variants().len() + 1);
+
+        let palettes = DmgPalette::cycle(PalettePreset::Pocket.palette());
+        assert_eq!(palettes.len(), PalettePreset::value_variants().len());
+        assert_eq!(palettes.iter().filter(|p| **p == PalettePreset::Pocket.palette()).count(), 1);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{
+    colorization::DmgPalettes,
+    layers::{Layer, Layers},
+    palette::DmgPalette,
+    logger::Logger,
     mmu::{MemoryArea, OamCorruptionCause},
+    renderer::Outline,
     HEIGHT, WIDTH,
 };
 use OamCorruptionCause::{IncDec, Read, ReadWrite, Write};
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 use HorizontalBlankPhase::*;
@@ -16,19 +22,40 @@ fn init_screen() -> [u8; 0x5A00 * 4] {
     [0; 0x5A00 * 4]
 }
 
+fn init_indexed() -> Vec<IndexedPixel> {
+    vec![IndexedPixel::default(); WIDTH * HEIGHT]
+}
+
+/// How the PPU draws the pixels of each line.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RenderMode {
+    /// Pixel FIFO and fetcher running every dot, showing the register writes made during mode 3
+    #[default]
+    Fifo,
+    /// Whole lines drawn at the end of mode 3, faster but blind to mid-line raster effects
+    Scanline,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct PixelProcessingUnit {
     oam_start_clock_count: usize,
     pub(crate) oam_corruption: Option<OamCorruptionCause>,
-    /// 8000-9FFF: Video RAM
+    /// 8000-9FFF: Video RAM, followed by the second bank used in CGB mode
     pub vram: Vec<u8>,
     /// FE00-FE9F: Sprite Attribute table
     pub oam: Vec<u8>,
     pub dma: u8,
     /// The cycle in which the last DMA transfer was requested.
     pub(crate) dma_started: usize,
-    /// If the DMA is running, including the initial delay.
+    /// If a DMA transfer was requested and is waiting for its initial delay.
+    pub(crate) dma_pending: bool,
+    /// If a DMA transfer is copying bytes. A restarted transfer keeps running until the new one
+    /// is done with its initial delay.
     pub(crate) dma_running: bool,
+    /// Start address of the running DMA transfer.
+    pub(crate) dma_source: usize,
+    /// Index of the next byte copied by the running DMA transfer.
+    pub(crate) dma_index: usize,
     /// Oam read is blocked
     pub(crate) dma_block_oam: bool,
 
@@ -41,12 +68,35 @@ pub struct PixelProcessingUnit {
     /// Each pixel is a shade of gray, from 0 to 3
     #[serde(skip, default = "init_screen")]
     pub screen: [u8; 0x5A00 * 4],
+    /// Every pixel of `screen` before the palettes turn it into a color, for the Super Game
+    /// Boy and tools that need the shades rather than the colors.
+    #[serde(skip, default = "init_indexed")]
+    pub indexed: Vec<IndexedPixel>,
     /// sprites that will be rendered in the next mode 3 scanline
     pub sprite_buffer: Vec<Sprite>,
     /// the length of the `sprite_buffer`
     pub sprite_buffer_len: u8,
+    /// Sprites found by the OAM search past the hardware limit, one bit for every OAM entry.
+    /// They are fetched without making mode 3 longer.
+    #[serde(skip)]
+    extra_sprites: u64,
+    /// Sprites found per line by the OAM search, raised above the hardware limit of 10 to
+    /// reduce flicker. A display setting, save states don't keep it.
+    #[serde(skip)]
+    pub(crate) sprite_limit: Option<u8>,
     /// Window Internal Line Counter
     pub wyc: u8,
+    /// The first frame after turning the LCD on is drawn but not shown.
+    blank_first_frame: bool,
+    /// Clock cycles since the LCD was turned off, or since the last blank frame shown while off.
+    #[serde(skip)]
+    lcd_off_ticks: usize,
+    /// A blank frame is due while the LCD is off.
+    #[serde(skip)]
+    blank_frame: bool,
+    /// Turning the LCD off outside VBlank was reported already. Some games do it every frame.
+    #[serde(skip)]
+    lcd_off_warned: bool,
 
     /// FF40: LCD Control Register
     pub lcdc: u8,
@@ -76,6 +126,35 @@ pub struct PixelProcessingUnit {
     pub wy: u8,
     /// FF4B: Window X Position
     pub wx: u8,
+    /// Game Boy Color mode, enabling the registers below and the CGB tile attributes.
+    pub(crate) cgb: bool,
+    /// FF4F: VRAM bank
+    pub vram_bank: u8,
+    /// FF68: Background palette index, bit 7 increments it after every BCPD write
+    pub bcps: u8,
+    /// FF6A: Object palette index, bit 7 increments it after every OCPD write
+    pub ocps: u8,
+    /// 8 background palettes of 4 RGB555 colors, accessed through FF69: BCPD
+    pub bg_palettes: Vec<u8>,
+    /// 8 object palettes of 4 RGB555 colors, accessed through FF6B: OCPD
+    pub obj_palettes: Vec<u8>,
+    /// DMG compatibility mode of the CGB, coloring BGP, OBP0 and OBP1 shades with the first
+    /// background palette and the first two object palettes.
+    pub(crate) colorized: bool,
+    /// Colors of the DMG shades, a display setting kept out of save states.
+    #[serde(skip)]
+    pub palette: DmgPalette,
+    /// Layers being drawn, also a display setting.
+    #[serde(skip)]
+    pub layers: Layers,
+    /// FF6C: Object priority mode, 0 prioritizes by OAM index and 1 by X coordinate like the DMG
+    pub opri: u8,
+    /// Makes LY always read as 0x90, as expected by Gameboy Doctor traces.
+    #[serde(skip)]
+    pub(crate) ly_stub: bool,
+    /// Renderer picked when the emulator starts, save states don't keep it.
+    #[serde(skip)]
+    pub(crate) render_mode: RenderMode,
 
     pub state: PpuState,
     /// When making the LY==LYC comparison, uses this value instead of ly to control the comparison
@@ -102,6 +181,8 @@ pub struct PixelProcessingUnit {
     /// the tile x position that the pixel fetcher is in
     fetcher_x: u8,
     fetch_tile_number: u8,
+    /// CGB attributes of the fetched tile, from the second VRAM bank
+    fetch_tile_attributes: u8,
     fetch_tile_data_low: u8,
     fetch_tile_data_high: u8,
 
@@ -129,6 +210,58 @@ pub struct Sprite {
     pub sy: u8,
     pub tile: u8,
     pub flags: u8,
+    /// Position in OAM, decides priority in CGB mode
+    pub index: u8,
+}
+
+/// Layer a pixel on screen came from.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum PixelSource {
+    #[default]
+    Background,
+    Window,
+    /// Sprite with attribute bit 4 clear, using OBP0 on the DMG. In CGB mode the color palette
+    /// is stored separately.
+    Object0,
+    /// Sprite with attribute bit 4 set, using OBP1 on the DMG
+    Object1,
+}
+
+impl PixelSource {
+    /// DMG object palette of sprite pixels.
+    pub fn object_palette(self) -> Option<u8> {
+        match self {
+            PixelSource::Background | PixelSource::Window => None,
+            PixelSource::Object0 => Some(0),
+            PixelSource::Object1 => Some(1),
+        }
+    }
+}
+
+/// Pixel as drawn, before a palette turns it into a color. Bits 0-1 hold the shade, bits 2-3
+/// the source layer and bits 4-6 the CGB palette.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct IndexedPixel(u8);
+
+impl IndexedPixel {
+    fn new(shade: u8, source: PixelSource, palette: u8) -> Self {
+        Self(shade | (source as u8) << 2 | palette << 4)
+    }
+
+    /// The shade after BGP, OBP0 or OBP1 on DMG, the color index in the palette in CGB mode.
+    pub fn shade(self) -> u8 {
+        self.0 & 0b11
+    }
+
+    pub fn source(self) -> PixelSource {
+        [PixelSource::Background, PixelSource::Window, PixelSource::Object0, PixelSource::Object1]
+            [(self.0 >> 2 & 0b11) as usize]
+    }
+
+    /// CGB background or object palette, always 0 on DMG.
+    pub fn palette(self) -> u8 {
+        self.0 >> 4 & 0x07
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -157,6 +290,8 @@ pub enum PixelTransferPhase {
     HighSpriteDataSetting,
     SpritePushing,
     EndTransfer,
+    /// Used instead of the states from WindowActivationCheck onwards by the scanline renderer.
+    ScanlineRendering,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug, Ord, PartialOrd, Eq)]
@@ -204,7 +339,9 @@ pub enum PpuState {
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Default, Clone, Debug)]
 pub struct PixelFifo {
-    queue: Vec<u8>,
+    /// Bits 0-1: color, bit 3: background priority, bit 4: DMG palette, bits 5-7: CGB palette,
+    /// bits 8-13: OAM index of sprite pixels, bit 14: background pixel fetched from the window
+    queue: Vec<u16>,
     /// next position to push
     head: u8,
     /// next position to pop
@@ -228,39 +365,57 @@ impl PixelFifo {
         self.tail = 0;
     }
 
-    fn push_background(&mut self, tile_low: u8, tile_high: u8) {
+    /// Background pixel from bit `x` of a tile row, as queued in the FIFO.
+    fn background_pixel(tile_low: u8, tile_high: u8, x: u8, attributes: u8, window: bool) -> u16 {
+        let priority = (attributes as u16 >> 7) << 3;
+        let palette = (attributes as u16 & 0x07) << 5;
+        let window = (window as u16) << 14;
+        let color = (((tile_high >> x) & 0x01) << 1) | ((tile_low >> x) & 0x01);
+        debug_assert!(color < 4);
+        color as u16 | priority | palette | window
+    }
+
+    /// Sprite pixel from bit `x` of a tile row, as queued in the FIFO.
+    fn sprite_pixel(tile_low: u8, tile_high: u8, x: u8, sprite: Sprite) -> u16 {
+        let color: u8 = (((tile_high >> x) & 0x01) << 1) | ((tile_low >> x) & 0x01);
+        debug_assert!(color < 4);
+
+        color as u16
+            | ((sprite.flags as u16 & 0x80) >> 4)
+            | (sprite.flags as u16 & 0x10)
+            | ((sprite.flags as u16 & 0x07) << 5)
+            | ((sprite.index as u16) << 8)
+    }
+
+    /// A sprite pixel replaces the one already mixed in when that one is transparent, or with
+    /// `index_priority` when it has a lower OAM index.
+    fn sprite_wins(new: u16, current: u16, index_priority: bool) -> bool {
+        current & 0b11 == 0 || (index_priority && new & 0b11 != 0 && new >> 8 < current >> 8)
+    }
+
+    fn push_background(&mut self, tile_low: u8, tile_high: u8, attributes: u8, window: bool) {
         for i in (0..8).rev() {
-            let color = (((tile_high >> i) & 0x01) << 1) | ((tile_low >> i) & 0x01);
-            debug_assert!(color < 4);
-            let pixel = color;
+            let pixel = Self::background_pixel(tile_low, tile_high, i, attributes, window);
             self.queue[self.head as usize] = pixel;
             self.head = (self.head + 1) % self.queue.len() as u8;
             debug_assert_ne!(self.head, self.tail);
         }
     }
 
-    fn push_sprite(
-        &mut self,
-        tile_low: u8,
-        tile_high: u8,
-        palette: bool,
-        background_priority: bool,
-    ) {
-        let pixel = |x| {
-            let color: u8 = (((tile_high >> x) & 0x01) << 1) | ((tile_low >> x) & 0x01);
-            debug_assert!(color < 4);
-
-            color | ((background_priority as u8) << 3) | ((palette as u8) << 4)
-        };
+    /// Mixes a sprite into the pixels already in the FIFO. With `index_priority`, sprites with a
+    /// lower OAM index win over the ones already pushed, like the CGB does.
+    fn push_sprite(&mut self, tile_low: u8, tile_high: u8, sprite: Sprite, index_priority: bool) {
+        let pixel = |x| Self::sprite_pixel(tile_low, tile_high, x, sprite);
 
         let mut cursor = self.tail;
         let mut x = 8u8;
-        // overwrite pixels in fifo, but only if 0
+        // overwrite pixels in fifo, but only if 0 or lower priority
         while cursor != self.head && x != 0 {
             x -= 1;
-            let color = self.queue[cursor as usize] & 0b11;
-            if color == 0 {
-                self.queue[cursor as usize] = pixel(x);
+            let current = self.queue[cursor as usize];
+            let new = pixel(x);
+            if Self::sprite_wins(new, current, index_priority) {
+                self.queue[cursor as usize] = new;
             }
             cursor = (cursor + 1) % self.queue.len() as u8;
         }
@@ -272,7 +427,7 @@ impl PixelFifo {
         }
     }
 
-    fn pop_front(&mut self) -> Option<u8> {
+    fn pop_front(&mut self) -> Option<u16> {
         if self.is_empty() {
             return None;
         }
@@ -287,12 +442,21 @@ impl MemoryArea for PixelProcessingUnit {
         let value = match address {
             0x8000..=0x9FFF if self.vram_read_block => 0xFF,
             0xFE00..=0xFE9F if self.dma_block_oam || self.oam_read_block => 0xFF,
-            0x8000..=0x9FFF => self.vram[address - 0x8000],
+            0x8000..=0x9FFF => self.vram[self.vram_index(address)],
             0xFE00..=0xFE9F => self.oam[address - 0xFE00],
+            0xFF4F if self.cgb => 0xFE | self.vram_bank,
+            0xFF68 if self.cgb => 0x40 | self.bcps,
+            0xFF69 if self.cgb && self.vram_read_block => 0xFF,
+            0xFF69 if self.cgb => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A if self.cgb => 0x40 | self.ocps,
+            0xFF6B if self.cgb && self.vram_read_block => 0xFF,
+            0xFF6B if self.cgb => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            0xFF6C if self.cgb => 0xFE | self.opri,
             0xFF40 => self.lcdc,
             0xFF41 => self.stat | 0x80,
             0xFF42 => self.scy,
             0xFF43 => self.scx,
+            0xFF44 if self.ly_stub => 0x90,
             0xFF44 => self.ly,
             0xFF45 => self.lyc,
             0xFF46 => self.dma,
@@ -309,14 +473,43 @@ impl MemoryArea for PixelProcessingUnit {
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
             0x8000..=0x9FFF if self.vram_write_block => (),
-            0xFE00..=0xFE9F if self.oam_write_block => (),
-            0x8000..=0x9FFF => self.vram[address - 0x8000] = value,
+            0xFE00..=0xFE9F if self.dma_block_oam || self.oam_write_block => (),
+            0x8000..=0x9FFF => {
+                let index = self.vram_index(address);
+                self.vram[index] = value
+            }
             0xFE00..=0xFE9F => self.oam[address - 0xFE00] = value,
             0xFF46 => self.start_dma(value),
+            0xFF4F if self.cgb => self.vram_bank = value & 0x01,
+            0xFF68 if self.cgb => self.bcps = value & 0xBF,
+            0xFF69 if self.cgb => {
+                if !self.vram_write_block {
+                    self.bg_palettes[(self.bcps & 0x3F) as usize] = value;
+                }
+                self.bcps = Self::increment_palette_index(self.bcps);
+            }
+            0xFF6A if self.cgb => self.ocps = value & 0xBF,
+            0xFF6B if self.cgb => {
+                if !self.vram_write_block {
+                    self.obj_palettes[(self.ocps & 0x3F) as usize] = value;
+                }
+                self.ocps = Self::increment_palette_index(self.ocps);
+            }
+            0xFF6C if self.cgb => self.opri = value & 0x01,
             0xFF40 => {
                 if value & 0x80 != self.lcdc & 0x80 {
                     if value & 0x80 == 0 {
-                        // disable ppu
+                        // disable ppu, which can damage the LCD of a DMG outside VBlank
+                        if self.stat & 0b11 != 1 && !self.lcd_off_warned {
+                            Logger::warn(format!(
+                                "LCD turned off outside VBlank, on line {} in mode {} (only reported once)",
+                                self.ly,
+                                self.stat & 0b11
+                            ));
+                            self.lcd_off_warned = true;
+                        }
+                        self.blank_screen();
+                        self.lcd_off_ticks = 0;
                         self.ly = 0;
                         self.line_start_ticks = 0;
                         // set to mode 0
@@ -328,6 +521,7 @@ impl MemoryArea for PixelProcessingUnit {
                         self.ly_for_compare = 0;
                         debug_assert_eq!(self.stat & 0b11, 0b00);
                         self.next_ticks = self.ticks;
+                        self.blank_first_frame = true;
                     }
                 }
                 self.lcdc = value
@@ -349,23 +543,33 @@ impl MemoryArea for PixelProcessingUnit {
 }
 
 impl PixelProcessingUnit {
-    pub fn new() -> Self {
+    pub fn new(render_mode: RenderMode) -> Self {
         Self {
             oam_start_clock_count: 0,
             oam_corruption: None,
-            vram: vec![0; 0x2000],
+            vram: vec![0; 0x4000],
             oam: vec![0; 0xA0],
             dma: 0xFF,
             dma_started: 0,
+            dma_pending: false,
             dma_running: false,
+            dma_source: 0,
+            dma_index: 0,
             dma_block_oam: false,
             oam_read_block: false,
             oam_write_block: false,
             vram_read_block: false,
             vram_write_block: false,
             screen: [0; 0x5A00 * 4],
-            sprite_buffer: vec![Sprite::default(); 10],
+            indexed: init_indexed(),
+            sprite_buffer: vec![Sprite::default(); SPRITES_PER_LINE as usize],
             sprite_buffer_len: 0,
+            blank_first_frame: false,
+            lcd_off_ticks: 0,
+            blank_frame: false,
+            lcd_off_warned: false,
+            extra_sprites: 0,
+            sprite_limit: None,
             wyc: 0,
             lcdc: 0x91,
             stat: 0x05,
@@ -378,12 +582,27 @@ impl PixelProcessingUnit {
             obp1: 0,
             wy: 0,
             wx: 0,
+            ly_stub: false,
+            render_mode,
+            cgb: false,
+            vram_bank: 0,
+            bcps: 0,
+            ocps: 0,
+            bg_palettes: vec![0xFF; 0x40],
+            obj_palettes: vec![0xFF; 0x40],
+            colorized: false,
+            palette: DmgPalette::default(),
+            layers: Layers::default(),
+            opri: 0,
             state: VerticalBlank(EndVBlank),
             ly_for_compare: 0,
 
+            // The DMG and MGB boot ROMs hand over in line 153, where LY already reads 0, and
+            // line 0 starts 100 clocks later. boot_hwio-dmgABCmgb passes from 0 to 190 clocks.
+            // The phase after the other boot ROMs isn't known, so they start the same way
             ticks: 0,
-            next_ticks: 23_440_377,
-            line_start_ticks: 23_435_361,
+            next_ticks: 100,
+            line_start_ticks: 0,
 
             background_fifo: PixelFifo::new(),
             sprite_fifo: PixelFifo::new(),
@@ -391,6 +610,7 @@ impl PixelProcessingUnit {
             fetcher_step: 0x03,
             fetcher_x: 0x14,
             fetch_tile_number: 0,
+            fetch_tile_attributes: 0,
             fetch_tile_data_low: 0,
             fetch_tile_data_high: 0,
 
@@ -412,6 +632,11 @@ impl PixelProcessingUnit {
     }
 
     fn search_objects(&mut self) {
+        let limit = self.sprite_limit.unwrap_or(SPRITES_PER_LINE).clamp(SPRITES_PER_LINE, 40);
+        if self.sprite_buffer.len() < limit as usize {
+            self.sprite_buffer.resize(limit as usize, Sprite::default());
+        }
+        self.extra_sprites = 0;
         self.sprite_buffer_len = 0;
         let sprite_height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
         for i in 0..40 {
@@ -428,30 +653,45 @@ impl PixelProcessingUnit {
                     sx,
                     tile: t,
                     flags,
+                    index: (i / 4) as u8,
                 };
+                if self.sprite_buffer_len >= SPRITES_PER_LINE {
+                    self.extra_sprites |= 1 << (i / 4);
+                }
                 self.sprite_buffer_len += 1;
             }
-            if self.sprite_buffer_len == 10 {
+            if self.sprite_buffer_len == limit {
                 break;
             }
         }
-        // sort buffer by priority, in increasing order
-        // lower x position, has greater priority
+        // sort buffer by fetching order, in increasing order
+        // lower x position is fetched first, and has greater priority on DMG
         self.sprite_buffer[0..self.sprite_buffer_len as usize].reverse();
         self.sprite_buffer[0..self.sprite_buffer_len as usize].sort_by_key(|x| !x.sx);
     }
 
+    /// The sprite was found past the hardware limit of sprites per line.
+    fn extra_sprite(&self, sprite: Sprite) -> bool {
+        self.extra_sprites & (1 << sprite.index) != 0
+    }
+
+    /// Index into `vram` of an address in the currently selected bank.
+    pub(crate) fn vram_index(&self, address: usize) -> usize {
+        self.vram_bank as usize * 0x2000 + address - 0x8000
+    }
+
+    fn increment_palette_index(index: u8) -> u8 {
+        if index & 0x80 != 0 {
+            0x80 | (index.wrapping_add(1) & 0x3F)
+        } else {
+            index
+        }
+    }
+
     pub fn start_dma(&mut self, value: u8) {
         self.dma = value;
         self.dma_started = self.ticks - 4;
-        if self.dma_running {
-            // HACK: if a DMA requested was make right before this one, this dma_started
-            // rewritten would cancel the oam_block of that DMA. To fix this, I will hackly
-            // block the oam here, but this will block the oam 4 cycles early, but I think
-            // this will not be observable.
-            self.dma_block_oam = true;
-        }
-        self.dma_running = true;
+        self.dma_pending = true;
     }
 
     pub fn machine_cycle(&mut self, ticks: usize) -> (bool, bool) {
@@ -460,8 +700,13 @@ impl PixelProcessingUnit {
         // Most of the ppu behaviour is based on the LIJI32/SameBoy including all of the timing,
         // and most of the implementation.
         if self.lcdc & 0x80 == 0 {
-            // ppu is disabled
+            // ppu is disabled, the screen stays blank and is handed over at the usual frame rate
             self.next_ticks = self.ticks;
+            self.lcd_off_ticks += ticks;
+            if self.lcd_off_ticks >= TICKS_PER_FRAME {
+                self.lcd_off_ticks -= TICKS_PER_FRAME;
+                self.blank_frame = true;
+            }
             return (false, false);
         }
 
@@ -604,7 +849,7 @@ impl PixelProcessingUnit {
                 self.sprite_fifo.clear();
 
                 // Fill background FIFO with 8 dummy pixels
-                self.background_fifo.push_background(0x00, 0x00);
+                self.background_fifo.push_background(0x00, 0x00, 0x00, false);
 
                 self.fetcher_step = 0;
                 self.fetcher_x = 0;
@@ -615,8 +860,16 @@ impl PixelProcessingUnit {
                 self.scanline_x = -((self.scx % 8 + 8) as i8) as u8;
                 self.sprite_at_0_penalty = (self.scx % 8).min(5);
 
+                if self.render_mode == RenderMode::Scanline {
+                    return (self.scanline_transfer_length(), PixelTransfer(ScanlineRendering));
+                }
                 (0, PixelTransfer(WindowActivationCheck))
             }
+            PixelTransfer(ScanlineRendering) => {
+                self.render_scanline();
+
+                (0, HorizontalBlank(StartHBlank))
+            }
             // Loop for every line from 0 to 144
             PixelTransfer(WindowActivationCheck) => {
                 let window_enabled = self.lcdc & 0x20 != 0;
@@ -686,6 +939,14 @@ impl PixelProcessingUnit {
                     && self.sprite_buffer[self.sprite_buffer_len as usize - 1].sx
                         == self.scanline_x.wrapping_add(8)
                 {
+                    let sprite = self.sprite_buffer[self.sprite_buffer_len as usize - 1];
+                    if self.extra_sprite(sprite) {
+                        // Fetched at once, past the limit the hardware has time for
+                        self.sprite_tile_address = self.sprite_row_address(sprite);
+                        self.sprite_tile_data_low = self.vram[self.sprite_tile_address as usize];
+                        self.sprite_tile_data_high = self.vram[self.sprite_tile_address as usize + 1];
+                        return (0, PixelTransfer(SpritePushing));
+                    }
                     (0, PixelTransfer(BackgroundFetching))
                 } else {
                     (0, PixelTransfer(EndTransfer))
@@ -719,20 +980,8 @@ impl PixelProcessingUnit {
             }
             PixelTransfer(SecondPixelFetching) => {
                 self.tick_pixel_fetcher(self.ly);
-                self.sprite_tile_address = {
-                    let tall = self.lcdc & 0x04 != 0;
-                    let sprite = self.sprite_buffer[self.sprite_buffer_len as usize - 1];
-                    let flip_y = sprite.flags & 0x40 != 0;
-
-                    let height = if tall { 0xF } else { 0x7 };
-                    let mut py = self.ly.wrapping_sub(sprite.sy) & height;
-                    if flip_y {
-                        py = (!py) & height;
-                    }
-
-                    let tile = if tall { sprite.tile & !1 } else { sprite.tile };
-                    tile as u16 * 0x10 + py as u16 * 2
-                };
+                self.sprite_tile_address =
+                    self.sprite_row_address(self.sprite_buffer[self.sprite_buffer_len as usize - 1]);
 
                 (2, PixelTransfer(LowSpriteDataSetting))
             }
@@ -747,17 +996,16 @@ impl PixelProcessingUnit {
                 (1, PixelTransfer(SpritePushing))
             }
             PixelTransfer(SpritePushing) => {
-                let flags = self.sprite_buffer[self.sprite_buffer_len as usize - 1].flags;
+                let sprite = self.sprite_buffer[self.sprite_buffer_len as usize - 1];
 
                 let [tile_low, tile_high] = [self.sprite_tile_data_low, self.sprite_tile_data_high]
-                    .map(|t| if flags & 0x20 != 0 { t.reverse_bits() } else { t });
-
-                self.sprite_fifo.push_sprite(
-                    tile_low,
-                    tile_high,
-                    flags & 0x10 != 0,
-                    flags & 0x80 != 0,
-                );
+                    .map(|t| if sprite.flags & 0x20 != 0 { t.reverse_bits() } else { t });
+
+                let index_priority = self.cgb && self.opri & 0x01 == 0;
+                // Hidden sprites are still fetched, keeping mode 3 as long
+                if self.layers.sprite_visible(sprite.index) {
+                    self.sprite_fifo.push_sprite(tile_low, tile_high, sprite, index_priority);
+                }
                 self.sprite_buffer_len -= 1;
 
                 // loop again
@@ -775,6 +1023,8 @@ impl PixelProcessingUnit {
                 }
             }
             HorizontalBlank(StartHBlank) => {
+                self.apply_palettes(self.ly as usize);
+
                 self.oam_read_block = false;
                 self.oam_write_block = false;
                 self.vram_read_block = false;
@@ -833,6 +1083,10 @@ impl PixelProcessingUnit {
             }
             VerticalBlank(FirstLineCheck) => {
                 if self.ly == HEIGHT as u8 {
+                    if self.blank_first_frame {
+                        self.blank_first_frame = false;
+                        self.blank_screen();
+                    }
                     self.set_stat_mode(1);
                     *vblank_interrupt = true;
                     if !self.stat_signal && self.stat & 0x20 != 0 {
@@ -1012,20 +1266,25 @@ impl PixelProcessingUnit {
                     }
                 }
                 let address = tile * 0x10 + 0x8000;
-                let offset = if is_in_window {
-                    2 * (ppu.wyc as u16 % 8)
+                let mut row = if is_in_window {
+                    ppu.wyc as u16 % 8
                 } else {
-                    2 * (ly.wrapping_add(ppu.scy) % 8) as u16
+                    (ly.wrapping_add(ppu.scy) % 8) as u16
                 };
+                if ppu.fetch_tile_attributes & 0x40 != 0 {
+                    row = 7 - row;
+                }
+                let bank = if ppu.fetch_tile_attributes & 0x08 != 0 { 0x2000 } else { 0 };
 
-                address + offset
+                address + bank + 2 * row
             };
 
         let push_to_fifo = |ppu: &mut PixelProcessingUnit| {
             if ppu.background_fifo.is_empty() {
-                let low = ppu.fetch_tile_data_low;
-                let high = ppu.fetch_tile_data_high;
-                ppu.background_fifo.push_background(low, high);
+                let attributes = ppu.fetch_tile_attributes;
+                let [low, high] = [ppu.fetch_tile_data_low, ppu.fetch_tile_data_high]
+                    .map(|t| if attributes & 0x20 != 0 { t.reverse_bits() } else { t });
+                ppu.background_fifo.push_background(low, high, attributes, ppu.is_in_window);
                 ppu.fetcher_step = 0;
             }
         };
@@ -1058,7 +1317,13 @@ impl PixelProcessingUnit {
                 };
 
                 let offset = (32 * ty as u16 + tx as u16) & 0x03ff;
-                self.fetch_tile_number = self.vram[(tile_map + offset) as usize - 0x8000];
+                let address = (tile_map + offset) as usize - 0x8000;
+                self.fetch_tile_number = self.vram[address];
+                self.fetch_tile_attributes = if self.cgb {
+                    self.vram[0x2000 + address]
+                } else {
+                    0
+                };
             }
             2 => {}
             // fetch tile data (low)
@@ -1103,39 +1368,325 @@ impl PixelProcessingUnit {
                 return;
             }
 
-            let i = (self.ly as usize) * WIDTH + self.screen_x as usize;
-            let background_enable = self.lcdc & 0x01 != 0;
-            let bcolor = if background_enable { pixel & 0b11 } else { 0 };
+            self.draw_pixel(self.screen_x as usize, pixel, sprite_pixel);
+            self.screen_x += 1;
+            self.scanline_x += 1;
+        }
+    }
+
+    /// Draws the pixel at `x` on the current line from the background and sprite pixels mixed
+    /// for it. It is colored once the line is done.
+    fn draw_pixel(&mut self, x: usize, pixel: u16, sprite_pixel: Option<u16>) {
+        let sprite_pixel = sprite_pixel.filter(|_| self.layers.objects);
+        let layer = if pixel & 0x4000 != 0 { Layer::Window } else { Layer::Background };
+        let pixel = if self.layers.visible(layer) { pixel } else { 0 };
+
+        let i = (self.ly as usize) * WIDTH + x;
+        self.indexed[i] = if self.cgb {
+            self.cgb_pixel_index(pixel, sprite_pixel)
+        } else {
+            self.dmg_pixel_index(pixel, sprite_pixel)
+        };
+    }
 
-            // background color, with pallete applied
-            let palette = self.bgp;
-            let mut color = (palette >> (bcolor * 2)) & 0b11;
+    /// Colors a line of `indexed` into `screen`, with the palettes as they are now. Palette
+    /// memory can't be written during mode 3, so a line sees the same colors throughout.
+    fn apply_palettes(&mut self, line: usize) {
+        for i in line * WIDTH..(line + 1) * WIDTH {
+            let Color { r, g, b, a } = self.pixel_color(self.indexed[i]);
+            self.screen[i * 4..i * 4 + 4].copy_from_slice(&[r, g, b, a]);
+        }
+    }
 
-            if let Some(sprite_pixel) = sprite_pixel {
-                let scolor = sprite_pixel & 0b11;
-                let background_priority = (sprite_pixel >> 3) & 0x01 != 0;
-                if scolor == 0 || background_priority && bcolor != 0 {
-                    // use background color
-                } else {
-                    // use sprite color
-                    let palette = (sprite_pixel >> 4) & 0x1;
-                    let palette = [self.obp0, self.obp1][palette as usize];
-                    color = (palette >> (scolor * 2)) & 0b11;
+    /// Clears the screen to the color the LCD shows when it has nothing to show, the lightest
+    /// DMG shade or white on the CGB, whatever the palettes in DMG compatibility mode.
+    fn blank_screen(&mut self) {
+        self.indexed.fill(IndexedPixel::default());
+        let Color { r, g, b, a } = if self.cgb || self.colorized {
+            Color { r: 0xFF, g: 0xFF, b: 0xFF, a: 0xFF }
+        } else {
+            self.dmg_pixel_color(0, None)
+        };
+        for pixel in self.screen.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[r, g, b, a]);
+        }
+    }
+
+    /// A blank frame is due while the LCD is off, to be shown in place of the last image.
+    pub(crate) fn take_blank_frame(&mut self) -> bool {
+        std::mem::take(&mut self.blank_frame)
+    }
+
+    /// Colors the last frame again with the DMG palette, so a palette change shows up right
+    /// away. CGB colors depend on the palette memory as it was for each line and are left alone.
+    pub fn recolor(&mut self) {
+        if self.cgb || self.colorized {
+            return;
+        }
+        for line in 0..HEIGHT {
+            self.apply_palettes(line);
+        }
+    }
+
+    fn pixel_color(&self, pixel: IndexedPixel) -> Color {
+        let object_palette = pixel.source().object_palette();
+        if !self.cgb {
+            return self.dmg_pixel_color(pixel.shade(), object_palette);
+        }
+        let palettes = if object_palette.is_some() { &self.obj_palettes } else { &self.bg_palettes };
+        Self::palette_color(palettes, pixel.palette(), pixel.shade())
+    }
+
+    /// RGB color a pixel from the given layer gets through the current palettes. On the DMG the
+    /// shade has BGP or OBPx applied already; on the CGB it's the color index in `palette`.
+    pub(crate) fn rgb(&self, shade: u8, source: PixelSource, palette: u8) -> [u8; 3] {
+        let Color { r, g, b, .. } = self.pixel_color(IndexedPixel::new(shade, source, palette));
+        [r, g, b]
+    }
+
+    /// VRAM index of the row of a sprite tile on the current line.
+    fn sprite_row_address(&self, sprite: Sprite) -> u16 {
+        let tall = self.lcdc & 0x04 != 0;
+        let flip_y = sprite.flags & 0x40 != 0;
+
+        let height = if tall { 0xF } else { 0x7 };
+        let mut py = self.ly.wrapping_sub(sprite.sy) & height;
+        if flip_y {
+            py = (!py) & height;
+        }
+
+        let tile = if tall { sprite.tile & !1 } else { sprite.tile };
+        let bank = if self.cgb && sprite.flags & 0x08 != 0 { 0x2000 } else { 0 };
+        bank + tile as u16 * 0x10 + py as u16 * 2
+    }
+
+    /// The window line counter moves on the current line. Like on the pixel FIFO, WX = 166 counts
+    /// the line without drawing any of the window.
+    fn window_line(&self) -> bool {
+        self.lcdc & 0x20 != 0 && self.reach_window && self.wx <= 166
+    }
+
+    /// The window shows up on the current line from the pixel at WX - 7.
+    fn scanline_window(&self) -> bool {
+        self.window_line() && self.wx < 166
+    }
+
+    /// Sprites fetched on the current line.
+    fn scanline_sprites(&self) -> impl Iterator<Item = Sprite> + '_ {
+        let enabled = self.lcdc & 0x02 != 0;
+        self.sprite_buffer[..self.sprite_buffer_len as usize]
+            .iter()
+            .rev()
+            .copied()
+            .filter(move |sprite| enabled && sprite.sx < WIDTH as u8 + 8)
+    }
+
+    /// Length of mode 3 for the scanline renderer, following the pixel FIFO: the pixels
+    /// discarded for scrolling and the window restarting the fetcher make it longer, and so does
+    /// every sprite fetched, waiting for the fetch of the tile under its leftmost pixel first.
+    fn scanline_transfer_length(&self) -> usize {
+        let window = self.scanline_window();
+        let (scx, wx) = (self.scx as usize, self.wx as usize);
+        let mut length = SCANLINE_TRANSFER_LENGTH + scx % 8;
+        if window {
+            length += 6;
+        }
+
+        let mut previous_tile = None;
+        for sprite in self.scanline_sprites().filter(|&sprite| !self.extra_sprite(sprite)) {
+            // Sprites are fetched from left to right, X is the leftmost pixel plus 8
+            let x = sprite.sx as usize;
+            let (in_window, position) = if window && x > wx { (true, x - 1 - wx) } else { (false, x + scx) };
+            let tile = Some((in_window, position / 8));
+            if tile != previous_tile {
+                length += 5usize.saturating_sub(position % 8);
+                previous_tile = tile;
+            }
+            length += 6;
+        }
+        length
+    }
+
+    /// Draws the current line at once, from the registers and VRAM as they are at the end of
+    /// mode 3.
+    fn render_scanline(&mut self) {
+        let window = self.scanline_window();
+        if self.window_line() {
+            // wrapping add, because wyc starts at -1
+            self.wyc = self.wyc.wrapping_add(1);
+        }
+
+        let mut sprite_line = [None; WIDTH];
+        let index_priority = self.cgb && self.opri & 0x01 == 0;
+        for sprite in self.scanline_sprites() {
+            // Hidden sprites are still fetched, keeping mode 3 as long
+            if !self.layers.sprite_visible(sprite.index) {
+                continue;
+            }
+            let address = self.sprite_row_address(sprite) as usize;
+            let [tile_low, tile_high] = [self.vram[address], self.vram[address + 1]]
+                .map(|t| if sprite.flags & 0x20 != 0 { t.reverse_bits() } else { t });
+
+            for column in 0..8u8 {
+                let x = (sprite.sx + column).wrapping_sub(8) as usize;
+                if sprite.sx + column < 8 || x >= WIDTH {
+                    continue;
+                }
+                let new = PixelFifo::sprite_pixel(tile_low, tile_high, 7 - column, sprite);
+                match sprite_line[x] {
+                    Some(current) if !PixelFifo::sprite_wins(new, current, index_priority) => {}
+                    _ => sprite_line[x] = Some(new),
                 }
             }
-            let Color { a, r, g, b } = match color {
-                0 => WHITE,
-                1 => LIGHT_GRAY,
-                2 => DARK_GRAY,
-                3 => BLACK,
-                _ => unreachable!(),
+        }
+
+        for (x, sprite_pixel) in sprite_line.into_iter().enumerate() {
+            let window_x = (x + 7).checked_sub(self.wx as usize).filter(|_| window);
+            let pixel = match window_x {
+                Some(column) => {
+                    let map = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+                    self.background_pixel(map, column as u8, self.wyc, true)
+                }
+                None => {
+                    let map = if self.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+                    let column = self.scx.wrapping_add(x as u8);
+                    self.background_pixel(map, column, self.ly.wrapping_add(self.scy), false)
+                }
             };
-            self.screen[i * 4] = r;
-            self.screen[(i * 4) + 1] = g;
-            self.screen[(i * 4) + 2] = b;
-            self.screen[(i * 4) + 3] = a;
-            self.screen_x += 1;
-            self.scanline_x += 1;
+            self.draw_pixel(x, pixel, sprite_pixel);
+        }
+
+        self.is_in_window = window;
+        self.screen_x = WIDTH as u8;
+        self.sprite_buffer_len = 0;
+    }
+
+    /// Pixel of the background or window at a position of the tile map, as queued in the FIFO.
+    fn background_pixel(&self, map: usize, column: u8, row: u8, window: bool) -> u16 {
+        let address = map + 32 * (row as usize / 8) + column as usize / 8 - 0x8000;
+        let mut tile = self.vram[address] as usize;
+        let attributes = if self.cgb { self.vram[0x2000 + address] } else { 0 };
+
+        if self.lcdc & 0x10 == 0 && tile < 0x80 {
+            tile += 0x100;
+        }
+        let mut tile_row = row as usize % 8;
+        if attributes & 0x40 != 0 {
+            tile_row = 7 - tile_row;
+        }
+        let bank = if attributes & 0x08 != 0 { 0x2000 } else { 0 };
+        let tile_address = bank + tile * 0x10 + 2 * tile_row;
+
+        let mut bit = 7 - column % 8;
+        if attributes & 0x20 != 0 {
+            bit = 7 - bit;
+        }
+        PixelFifo::background_pixel(self.vram[tile_address], self.vram[tile_address + 1], bit, attributes, window)
+    }
+
+    /// Shade of a DMG pixel after BGP, OBP0 or OBP1, with the layer it came from.
+    fn dmg_pixel_index(&self, pixel: u16, sprite_pixel: Option<u16>) -> IndexedPixel {
+        let background_enable = self.lcdc & 0x01 != 0;
+        let bcolor = if background_enable { pixel as u8 & 0b11 } else { 0 };
+
+        // background color, with pallete applied
+        let palette = self.bgp;
+        let mut color = (palette >> (bcolor * 2)) & 0b11;
+        let mut source = if pixel & 0x4000 != 0 { PixelSource::Window } else { PixelSource::Background };
+
+        if let Some(sprite_pixel) = sprite_pixel {
+            let scolor = sprite_pixel as u8 & 0b11;
+            let background_priority = (sprite_pixel >> 3) & 0x01 != 0;
+            if scolor == 0 || background_priority && bcolor != 0 {
+                // use background color
+            } else {
+                // use sprite color
+                let palette = (sprite_pixel >> 4) & 0x1;
+                source = [PixelSource::Object0, PixelSource::Object1][palette as usize];
+                let palette = [self.obp0, self.obp1][palette as usize];
+                color = (palette >> (scolor * 2)) & 0b11;
+            }
+        }
+
+        IndexedPixel::new(color, source, 0)
+    }
+
+    fn dmg_pixel_color(&self, color: u8, object_palette: Option<u8>) -> Color {
+        if self.colorized {
+            return match object_palette {
+                Some(palette) => Self::palette_color(&self.obj_palettes, palette, color),
+                None => Self::palette_color(&self.bg_palettes, 0, color),
+            };
+        }
+
+        let [r, g, b] = self.palette.ramp(object_palette)[color as usize];
+        Color { r, g, b, a: 255 }
+    }
+
+    /// Color index of a CGB pixel, with the layer and palette it came from.
+    fn cgb_pixel_index(&self, pixel: u16, sprite_pixel: Option<u16>) -> IndexedPixel {
+        let bcolor = pixel as u8 & 0b11;
+        let palette = (pixel >> 5) as u8 & 0x07;
+
+        if let Some(sprite_pixel) = sprite_pixel {
+            let scolor = sprite_pixel as u8 & 0b11;
+            // LCDC bit 0 clear puts every sprite above the background
+            let master_priority = self.lcdc & 0x01 != 0;
+            let background_priority = (pixel | sprite_pixel) & 0x08 != 0;
+            if scolor != 0 && (!master_priority || bcolor == 0 || !background_priority) {
+                let palette = (sprite_pixel >> 5) as u8 & 0x07;
+                let source = [PixelSource::Object0, PixelSource::Object1][(sprite_pixel >> 4) as usize & 0x01];
+                return IndexedPixel::new(scolor, source, palette);
+            }
+        }
+
+        let source = if pixel & 0x4000 != 0 { PixelSource::Window } else { PixelSource::Background };
+        IndexedPixel::new(bcolor, source, palette)
+    }
+
+    /// Bounds of the OAM entries that aren't hidden, to outline them on top of the frame.
+    pub fn sprite_boxes(&self) -> Vec<Outline> {
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        (0..40)
+            .filter(|&index| self.layers.sprite_visible(index as u8))
+            .map(|index| Outline {
+                left: self.oam[index * 4 + 1] as i32 - 8,
+                top: self.oam[index * 4] as i32 - 16,
+                width: 8,
+                height,
+            })
+            .collect()
+    }
+
+    /// Loads the palettes used in DMG compatibility mode, as the CGB boot ROM does.
+    pub fn colorize(&mut self, palettes: DmgPalettes) {
+        Self::load_palette(&mut self.bg_palettes, 0, palettes.bg);
+        Self::load_palette(&mut self.obj_palettes, 0, palettes.obj0);
+        Self::load_palette(&mut self.obj_palettes, 1, palettes.obj1);
+        self.colorized = true;
+    }
+
+    fn load_palette(palettes: &mut [u8], palette: usize, colors: [u16; 4]) {
+        for (i, color) in colors.iter().enumerate() {
+            let index = palette * 8 + i * 2;
+            palettes[index..index + 2].copy_from_slice(&color.to_le_bytes());
+        }
+    }
+
+    /// Converts a RGB555 color from CGB palette memory.
+    fn palette_color(palettes: &[u8], palette: u8, color: u8) -> Color {
+        let index = palette as usize * 8 + color as usize * 2;
+        let rgb = u16::from_le_bytes([palettes[index], palettes[index + 1]]);
+        let channel = |shift: u16| {
+            let c = ((rgb >> shift) & 0x1F) as u8;
+            (c << 3) | (c >> 2)
+        };
+
+        Color {
+            r: channel(0),
+            g: channel(5),
+            b: channel(10),
+            a: 255,
         }
     }
 }
@@ -1147,27 +1698,256 @@ impl From<Color> for u32 {
     }
 }
 
-const WHITE: Color = Color {
-    r: 224,
-    g: 248,
-    b: 208,
-    a: 255,
-};
-const LIGHT_GRAY: Color = Color {
-    r: 136,
-    g: 192,
-    b: 112,
-    a: 255,
-};
-const DARK_GRAY: Color = Color {
-    r: 39,
-    g: 80,
-    b: 70,
-    a: 255,
-};
-const BLACK: Color = Color {
-    r: 8,
-    g: 24,
-    b: 32,
-    a: 255,
-};
+/// Clock cycles between two frames.
+const TICKS_PER_FRAME: usize = 70224;
+
+/// Sprites the OAM search finds per line on the hardware.
+const SPRITES_PER_LINE: u8 = 10;
+
+/// Dots mode 3 takes without scrolling, window or sprites, counted from the FIFO being cleared.
+const SCANLINE_TRANSFER_LENGTH: usize = 167;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MemoryManagementUnit;
+    use crate::model::Model;
+
+    fn cgb_ppu() -> PixelProcessingUnit {
+        let mut ppu = PixelProcessingUnit::new(RenderMode::Fifo);
+        ppu.cgb = true;
+        ppu
+    }
+
+    #[test]
+    fn bcps_auto_increment_wraps_around() {
+        let mut ppu = cgb_ppu();
+        ppu.write(0xFF68, 0xBE);
+        for value in [0x12, 0x34, 0x56] {
+            ppu.write(0xFF69, value);
+        }
+
+        assert_eq!(ppu.bg_palettes[0x3E], 0x12);
+        assert_eq!(ppu.bg_palettes[0x3F], 0x34);
+        assert_eq!(ppu.bg_palettes[0x00], 0x56);
+        assert_eq!(ppu.read(0xFF68), Some(0xC1));
+        assert_eq!(ppu.read(0xFF69), Some(0xFF));
+    }
+
+    #[test]
+    fn ocps_without_auto_increment_stays() {
+        let mut ppu = cgb_ppu();
+        ppu.write(0xFF6A, 0x05);
+        ppu.write(0xFF6B, 0x12);
+        ppu.write(0xFF6B, 0x34);
+
+        assert_eq!(ppu.obj_palettes[0x05], 0x34);
+        assert_eq!(ppu.obj_palettes[0x06], 0xFF);
+        assert_eq!(ppu.read(0xFF6A), Some(0x45));
+        assert_eq!(ppu.read(0xFF6B), Some(0x34));
+    }
+
+    /// Draws a frame with OAM entries 0 and 1 overlapping at the top left corner, entry 0 in
+    /// shade 3 with OBP0 and entry 1 in shade 1 with OBP1, and returns the pixel there.
+    fn overlapping_sprites(render_mode: RenderMode, hidden: &[u8]) -> IndexedPixel {
+        let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+        let ppu = &mut mmu.ppu;
+        ppu.render_mode = render_mode;
+        ppu.vram[0x10..0x20].fill(0xFF);
+        for row in ppu.vram[0x20..0x30].chunks_mut(2) {
+            row[0] = 0xFF;
+        }
+        ppu.oam[0..8].copy_from_slice(&[16, 8, 0x01, 0x00, 16, 8, 0x02, 0x10]);
+        ppu.obp0 = 0xE4;
+        ppu.obp1 = 0xE4;
+        ppu.lcdc = 0x93;
+        for &index in hidden {
+            ppu.layers.set_sprite_visible(index, false);
+        }
+
+        let frames = mmu.frames;
+        while mmu.frames == frames {
+            mmu.cycle(4);
+        }
+        mmu.ppu.indexed[0]
+    }
+
+    #[test]
+    fn hidden_sprites_let_lower_priority_sprites_through() {
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            let front = overlapping_sprites(render_mode, &[]);
+            assert_eq!((front.shade(), front.source()), (3, PixelSource::Object0));
+
+            let behind = overlapping_sprites(render_mode, &[0]);
+            assert_eq!((behind.shade(), behind.source()), (1, PixelSource::Object1));
+
+            let background = overlapping_sprites(render_mode, &[0, 1]);
+            assert_eq!((background.shade(), background.source()), (0, PixelSource::Background));
+        }
+    }
+
+    #[test]
+    fn sprite_boxes_skip_hidden_entries() {
+        let mut ppu = PixelProcessingUnit::new(RenderMode::Fifo);
+        ppu.oam[0..8].copy_from_slice(&[16, 8, 0, 0, 32, 20, 0, 0]);
+        ppu.layers.set_sprite_visible(0, false);
+        (2..40).for_each(|index| ppu.layers.set_sprite_visible(index, false));
+
+        let boxes = ppu.sprite_boxes();
+        assert_eq!(boxes, [Outline { left: 12, top: 16, width: 8, height: 8 }]);
+        ppu.lcdc |= 0x04;
+        assert_eq!(ppu.sprite_boxes()[0].height, 16);
+    }
+
+    /// Runs the MMU until the next frame is handed to the renderer and returns it.
+    fn next_frame(mmu: &mut MemoryManagementUnit) -> Vec<u8> {
+        let frames = mmu.frames;
+        while mmu.frames == frames {
+            mmu.cycle(4);
+        }
+        mmu.ppu.screen.to_vec()
+    }
+
+    fn filled(color: [u8; 3]) -> Vec<u8> {
+        [color[0], color[1], color[2], 0xFF].repeat(WIDTH * HEIGHT)
+    }
+
+    #[test]
+    fn blank_frames_are_shown_while_lcd_is_off() {
+        let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+        mmu.write(0xFF47u16, 0xFFu8);
+        mmu.write(0xFF40u16, 0x91u8);
+        let black = mmu.ppu.rgb(3, PixelSource::Background, 0);
+        assert_eq!(next_frame(&mut mmu), filled(black));
+
+        mmu.write(0xFF40u16, 0x11u8);
+        let white = mmu.ppu.rgb(0, PixelSource::Background, 0);
+        for _ in 0..3 {
+            assert_eq!(next_frame(&mut mmu), filled(white));
+        }
+    }
+
+    #[test]
+    fn lcd_off_is_white_in_dmg_compatibility_mode() {
+        let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+        let gray = 0x4210;
+        mmu.ppu.colorize(DmgPalettes {
+            bg: [gray; 4],
+            obj0: [gray; 4],
+            obj1: [gray; 4],
+        });
+        mmu.write(0xFF40u16, 0x11u8);
+        assert_eq!(next_frame(&mut mmu), filled([0xFF; 3]));
+    }
+
+    #[test]
+    fn first_frame_after_lcd_is_turned_on_is_blank() {
+        let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+        mmu.write(0xFF47u16, 0xFFu8);
+        mmu.write(0xFF40u16, 0x11u8);
+        next_frame(&mut mmu);
+
+        mmu.write(0xFF40u16, 0x91u8);
+        let white = mmu.ppu.rgb(0, PixelSource::Background, 0);
+        let black = mmu.ppu.rgb(3, PixelSource::Background, 0);
+        assert_eq!(next_frame(&mut mmu), filled(white));
+        assert_eq!(next_frame(&mut mmu), filled(black));
+    }
+
+    #[test]
+    fn draw_pixel_keeps_layer_for_recoloring() {
+        let mut ppu = PixelProcessingUnit::new(RenderMode::Fifo);
+        ppu.lcdc = 0x91;
+        ppu.bgp = 0xE4;
+        ppu.obp0 = 0x1B;
+        ppu.obp1 = 0xE4;
+        ppu.draw_pixel(0, 0x0001, None);
+        ppu.draw_pixel(1, 0x4002, None);
+        ppu.draw_pixel(2, 0x0000, Some(0x0001));
+        ppu.draw_pixel(3, 0x0000, Some(0x0013));
+
+        let drawn: Vec<_> = ppu.indexed[..4].iter().map(|pixel| (pixel.shade(), pixel.source())).collect();
+        assert_eq!(
+            drawn,
+            [
+                (1, PixelSource::Background),
+                (2, PixelSource::Window),
+                (2, PixelSource::Object0),
+                (3, PixelSource::Object1),
+            ]
+        );
+
+        // A ramp per layer, with the shade in the red channel
+        let ramp = |green: u8| [0, 1, 2, 3].map(|shade| [shade, green, 0]);
+        ppu.palette = DmgPalette {
+            bg: ramp(0x10),
+            obj0: ramp(0x20),
+            obj1: ramp(0x30),
+        };
+        ppu.recolor();
+        let colors: Vec<_> = ppu.screen[..16].chunks(4).map(|pixel| pixel.to_vec()).collect();
+        assert_eq!(
+            colors,
+            [
+                [1, 0x10, 0, 0xFF],
+                [2, 0x10, 0, 0xFF],
+                [2, 0x20, 0, 0xFF],
+                [3, 0x30, 0, 0xFF],
+            ]
+        );
+    }
+
+    /// Places 12 sprites side by side on the first lines, in shade 3 over a blank background.
+    fn twelve_sprites(mmu: &mut MemoryManagementUnit) {
+        let ppu = &mut mmu.ppu;
+        ppu.vram[0x10..0x20].fill(0xFF);
+        for (index, entry) in ppu.oam.chunks_exact_mut(4).take(12).enumerate() {
+            entry.copy_from_slice(&[16, 8 + index as u8 * 8, 0x01, 0x00]);
+        }
+        ppu.obp0 = 0xE4;
+        ppu.lcdc = 0x93;
+    }
+
+    #[test]
+    fn sprite_limit_never_goes_below_hardware_limit() {
+        let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+        twelve_sprites(&mut mmu);
+        let ppu = &mut mmu.ppu;
+
+        for (limit, found) in [(None, 10), (Some(4), 10), (Some(40), 12)] {
+            ppu.sprite_limit = limit;
+            ppu.search_objects();
+            assert_eq!(ppu.sprite_buffer_len, found);
+        }
+        assert_eq!(ppu.extra_sprites, 0b11 << 10);
+    }
+
+    #[test]
+    fn extra_sprites_dont_lengthen_mode_3() {
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            let mut lengths = vec![];
+            for (limit, shade) in [(Some(10), 0), (Some(40), 3)] {
+                let mut mmu = MemoryManagementUnit::for_rom(vec![0; 0x8000], Model::Dmg);
+                twelve_sprites(&mut mmu);
+                mmu.ppu.render_mode = render_mode;
+                mmu.ppu.sprite_limit = limit;
+
+                // Clocks spent in mode 3 on line 1, 4 at a time
+                let mut length = 0;
+                while mmu.ppu.ly != 2 {
+                    mmu.cycle(4);
+                    if mmu.ppu.ly == 1 && mmu.ppu.stat & 0b11 == 3 {
+                        length += 4;
+                    }
+                }
+                lengths.push(length);
+
+                // The 12th sprite is only drawn past the hardware limit
+                next_frame(&mut mmu);
+                assert_eq!(mmu.ppu.indexed[WIDTH + 88].shade(), shade);
+            }
+            assert!(lengths[0] > 0);
+            assert_eq!(lengths[0], lengths[1]);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::write;
+use std::path::PathBuf;
+
+use crate::instruction::Command;
+use crate::instruction::Command::{CallCcU16, CallU16, Ret, RetCc, Reti, Rst};
+use crate::logger::Logger;
+use crate::symbols::Symbols;
+
+/// Deepest call stack tracked, games that unbalance the stack would otherwise grow it forever.
+const MAX_CALL_DEPTH: usize = 256;
+
+type Location = (u16, u16);
+
+#[derive(Default, Copy, Clone, Debug)]
+struct FunctionCycles {
+    calls: u64,
+    exclusive: u64,
+    inclusive: u64,
+}
+
+struct StackFrame {
+    function: Location,
+    entered_at: u64,
+}
+
+/// Attributes elapsed machine cycles to the bank:PC that consumed them, and to the functions
+/// entered through CALL, RST or interrupts.
+pub struct Profiler {
+    path: PathBuf,
+    total: u64,
+    last: Option<Location>,
+    instructions: HashMap<Location, u64>,
+    functions: HashMap<Location, FunctionCycles>,
+    call_stack: Vec<StackFrame>,
+}
+
+impl Profiler {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            total: 0,
+            last: None,
+            instructions: HashMap::new(),
+            functions: HashMap::new(),
+            call_stack: vec![],
+        }
+    }
+
+    /// Records an executed instruction. `old_sp` is the stack pointer before it was executed,
+    /// used to tell whether conditional calls and returns were taken.
+    pub fn instruction(
+        &mut self,
+        location: Location,
+        command: Command,
+        cycles: u8,
+        old_sp: u16,
+        sp: u16,
+        target: Location,
+    ) {
+        if self.call_stack.is_empty() {
+            self.enter(location);
+        }
+        self.attribute(location, cycles as u64);
+
+        match command {
+            CallU16(..) | CallCcU16(..) | Rst(..) if sp == old_sp.wrapping_sub(2) => {
+                self.enter(target)
+            }
+            Ret | RetCc(..) | Reti if sp == old_sp.wrapping_add(2) => self.leave(),
+            _ => {}
+        }
+    }
+
+    /// Records the dispatch of an interrupt, which enters the handler at `vector`.
+    pub fn interrupt(&mut self, vector: u16, cycles: u8) {
+        self.enter((0, vector));
+        self.attribute((0, vector), cycles as u64);
+    }
+
+    /// Records cycles spent halted, attributed to the HALT instruction.
+    pub fn idle(&mut self, cycles: u8) {
+        if let Some(location) = self.last {
+            self.attribute(location, cycles as u64);
+        }
+    }
+
+    fn attribute(&mut self, location: Location, cycles: u64) {
+        self.total += cycles;
+        self.last = Some(location);
+        *self.instructions.entry(location).or_default() += cycles;
+        if let Some(frame) = self.call_stack.last() {
+            self.functions.entry(frame.function).or_default().exclusive += cycles;
+        }
+    }
+
+    fn enter(&mut self, function: Location) {
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            let frame = self.call_stack.remove(0);
+            self.close(frame);
+        }
+        self.functions.entry(function).or_default().calls += 1;
+        self.call_stack.push(StackFrame {
+            function,
+            entered_at: self.total,
+        });
+    }
+
+    fn leave(&mut self) {
+        // Keep the outermost frame, returns past it come from manual stack manipulation
+        if self.call_stack.len() > 1 {
+            let frame = self.call_stack.pop().unwrap();
+            self.close(frame);
+        }
+    }
+
+    fn close(&mut self, frame: StackFrame) {
+        self.functions.entry(frame.function).or_default().inclusive +=
+            self.total - frame.entered_at;
+    }
+
+    /// Writes the report sorted by cycles, closing any functions still on the stack.
+    pub fn save(&mut self, symbols: Option<&Symbols>, frames: usize) {
+        while let Some(frame) = self.call_stack.pop() {
+            self.close(frame);
+        }
+
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.total.max(1) as f64;
+        let describe = |(bank, address): Location| Symbols::describe(symbols, bank, address);
+        let mut report = String::new();
+
+        writeln!(report, "Total: {} M-cycles over {} frames", self.total, frames).unwrap();
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, cycles)| Reverse(cycles.inclusive));
+
+        writeln!(report, "\nFunctions").unwrap();
+        writeln!(
+            report,
+            "{:>12} {:>7} {:>12} {:>7} {:>9}  Entry",
+            "Inclusive", "%", "Self", "%", "Calls"
+        )
+        .unwrap();
+        for (&location, cycles) in functions {
+            writeln!(
+                report,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>9}  {}",
+                cycles.inclusive,
+                percent(cycles.inclusive),
+                cycles.exclusive,
+                percent(cycles.exclusive),
+                cycles.calls,
+                describe(location)
+            )
+            .unwrap();
+        }
+
+        let mut instructions: Vec<_> = self.instructions.iter().collect();
+        instructions.sort_by_key(|(_, &cycles)| Reverse(cycles));
+
+        writeln!(report, "\nHot spots").unwrap();
+        writeln!(report, "{:>12} {:>7}  Address", "Cycles", "%").unwrap();
+        for (&location, &cycles) in instructions {
+            writeln!(
+                report,
+                "{:>12} {:>6.2}%  {}",
+                cycles,
+                percent(cycles),
+                describe(location)
+            )
+            .unwrap();
+        }
+
+        match write(&self.path, report) {
+            Ok(()) => Logger::info(format!("Profile saved to {}", self.path.display())),
+            Err(e) => Logger::error(format!("Unable to save profile {}: {e}", self.path.display())),
+        }
+    }
+}
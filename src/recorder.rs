@@ -0,0 +1,435 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use clap::ValueEnum;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageResult, RgbaImage};
+
+use crate::logger::Logger;
+use crate::mmu::MemoryManagementUnit;
+use crate::screenshot;
+
+/// Clock cycles between two VBlanks, which makes the frame rate 59.73 FPS.
+const CYCLES_PER_FRAME: u32 = 70224;
+const CYCLES_PER_SECOND: u32 = 4194304;
+
+/// File formats gameplay can be recorded to.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RecordFormat {
+    /// Uncompressed AVI video with the sound in a WAV file next to it
+    #[default]
+    Avi,
+    /// Animated GIF without sound, holding repeated frames instead of storing them again
+    Gif,
+}
+
+impl RecordFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Avi => "avi",
+            RecordFormat::Gif => "gif",
+        }
+    }
+}
+
+/// A frame and the sound samples generated since the previous one, as interleaved
+/// left and right values.
+struct Clip {
+    image: RgbaImage,
+    samples: Vec<f32>,
+}
+
+/// Records every frame rendered while it runs. The files are written by a background thread,
+/// so encoding never holds up the emulation.
+pub struct Recorder {
+    sender: Sender<Clip>,
+    writer: JoinHandle<ImageResult<()>>,
+    /// Name of the video file, without the WAV next to it.
+    path: String,
+}
+
+impl Recorder {
+    /// Creates the recording files next to the ROM, named after the current time, and starts
+    /// generating the sound in emulated time, so that it stays in step with the frames.
+    pub fn start(mmu: &mut MemoryManagementUnit, rom_path: &str, format: RecordFormat) -> ImageResult<Recorder> {
+        let base = format!("{rom_path}.{}", screenshot::timestamp());
+        let frame = screenshot::capture(mmu, 1);
+        let (width, height) = frame.dimensions();
+
+        let path = format!("{base}.{}", format.extension());
+
+        let mut output: Box<dyn Output> = match format {
+            RecordFormat::Avi => Box::new(AviOutput {
+                video: Avi::create(&path, width, height)?,
+                sound: Wav::create(&format!("{base}.wav"), mmu.apu.sample_rate())?,
+            }),
+            RecordFormat::Gif => Box::new(Gif::create(&path)?),
+        };
+
+        let (sender, receiver) = channel::<Clip>();
+        let writer = thread::spawn(move || {
+            for clip in receiver {
+                output.frame(clip)?;
+            }
+            output.finish()
+        });
+
+        mmu.apu.set_recording(true);
+        Ok(Recorder { sender, writer, path })
+    }
+
+    /// Queues the last frame rendered along with the sound generated since the previous one.
+    pub fn frame(&self, mmu: &MemoryManagementUnit) {
+        let clip = Clip {
+            image: screenshot::capture(mmu, 1),
+            samples: mmu.apu.take_recorded_samples(),
+        };
+        if self.sender.send(clip).is_err() {
+            Logger::error("Recording stopped writing frames");
+        }
+    }
+
+    /// Writes the queued frames and closes the files. Returns the name of the video file.
+    pub fn stop(self, mmu: &mut MemoryManagementUnit) -> ImageResult<String> {
+        mmu.apu.set_recording(false);
+        drop(self.sender);
+        self.writer.join().expect("Recording thread panicked")?;
+        Ok(self.path)
+    }
+}
+
+trait Output: Send {
+    fn frame(&mut self, clip: Clip) -> ImageResult<()>;
+
+    fn finish(&mut self) -> ImageResult<()>;
+}
+
+struct AviOutput {
+    video: Avi,
+    sound: Wav,
+}
+
+impl Output for AviOutput {
+    fn frame(&mut self, clip: Clip) -> ImageResult<()> {
+        self.video.frame(&clip.image)?;
+        self.sound.samples(&clip.samples)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ImageResult<()> {
+        self.video.finish()?;
+        self.sound.finish()?;
+        Ok(())
+    }
+}
+
+/// Uncompressed 24 bit RGB AVI, so every frame is stored exactly as rendered.
+struct Avi {
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    frames: u32,
+    /// idx1 entries, pointing to each frame inside the movi list.
+    index: Vec<u8>,
+}
+
+impl Avi {
+    /// Size of the RIFF, hdrl and movi headers written before the first frame.
+    const HEADER_SIZE: u32 = 224;
+
+    fn create(path: &str, width: u32, height: u32) -> std::io::Result<Avi> {
+        let mut avi = Avi { file: BufWriter::new(File::create(path)?), width, height, frames: 0, index: vec![] };
+        let header = avi.header();
+        avi.file.write_all(&header)?;
+        Ok(avi)
+    }
+
+    fn frame_size(&self) -> u32 {
+        self.width * self.height * 3
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let frame_size = self.frame_size();
+        let movi_size = 4 + self.frames * (8 + frame_size);
+        let riff_size = Self::HEADER_SIZE - 8 + movi_size - 4 + 8 + self.index.len() as u32;
+
+        let mut main_header = vec![];
+        let micros_per_frame = (1_000_000 * CYCLES_PER_FRAME as u64 / CYCLES_PER_SECOND as u64) as u32;
+        for value in [
+            micros_per_frame,
+            frame_size * 60, // Maximum bytes per second
+            0,               // Padding granularity
+            0x10,            // Has an index
+            self.frames,
+            0, // Initial frames
+            1, // Streams
+            frame_size,
+            self.width,
+            self.height,
+            0, 0, 0, 0,
+        ] {
+            main_header.extend(value.to_le_bytes());
+        }
+
+        let mut stream_header = b"vidsDIB ".to_vec();
+        for value in [0, 0, 0, CYCLES_PER_FRAME, CYCLES_PER_SECOND, 0, self.frames, frame_size, u32::MAX, 0] {
+            stream_header.extend(value.to_le_bytes());
+        }
+        for value in [0, 0, self.width as u16, self.height as u16] {
+            stream_header.extend(value.to_le_bytes());
+        }
+
+        // BITMAPINFOHEADER, a positive height stores the rows bottom-up
+        let mut stream_format = vec![];
+        for value in [40, self.width, self.height] {
+            stream_format.extend(value.to_le_bytes());
+        }
+        stream_format.extend(1u16.to_le_bytes());
+        stream_format.extend(24u16.to_le_bytes());
+        for value in [0, frame_size, 0, 0, 0, 0] {
+            stream_format.extend(value.to_le_bytes());
+        }
+
+        let stream_list = [chunk(b"strh", &stream_header), chunk(b"strf", &stream_format)].concat();
+        let header_list = [chunk(b"avih", &main_header), list(b"strl", &stream_list)].concat();
+
+        let mut header = b"RIFF".to_vec();
+        header.extend(riff_size.to_le_bytes());
+        header.extend(b"AVI ");
+        header.extend(list(b"hdrl", &header_list));
+        header.extend(b"LIST");
+        header.extend(movi_size.to_le_bytes());
+        header.extend(b"movi");
+        header
+    }
+
+    fn frame(&mut self, image: &RgbaImage) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(self.frame_size() as usize);
+        for row in image.rows().rev() {
+            for pixel in row {
+                data.extend([pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+
+        // Offsets are counted from the movi tag
+        self.index.extend(b"00db");
+        self.index.extend(0x10u32.to_le_bytes());
+        self.index.extend((4 + self.frames * (8 + self.frame_size())).to_le_bytes());
+        self.index.extend(self.frame_size().to_le_bytes());
+
+        self.file.write_all(&chunk(b"00db", &data))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.file.write_all(&chunk(b"idx1", &self.index))?;
+        let header = self.header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()
+    }
+}
+
+/// 16 bit stereo PCM at the sample rate of the audio device.
+struct Wav {
+    file: BufWriter<File>,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl Wav {
+    fn create(path: &str, sample_rate: u32) -> std::io::Result<Wav> {
+        let mut wav = Wav { file: BufWriter::new(File::create(path)?), sample_rate, data_size: 0 };
+        let header = wav.header();
+        wav.file.write_all(&header)?;
+        Ok(wav)
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut format = vec![];
+        format.extend(1u16.to_le_bytes()); // PCM
+        format.extend(2u16.to_le_bytes());
+        format.extend(self.sample_rate.to_le_bytes());
+        format.extend((self.sample_rate * 4).to_le_bytes());
+        format.extend(4u16.to_le_bytes());
+        format.extend(16u16.to_le_bytes());
+
+        let mut header = b"RIFF".to_vec();
+        header.extend((36 + self.data_size).to_le_bytes());
+        header.extend(b"WAVE");
+        header.extend(chunk(b"fmt ", &format));
+        header.extend(b"data");
+        header.extend(self.data_size.to_le_bytes());
+        header
+    }
+
+    fn samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let header = self.header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()
+    }
+}
+
+/// Animated GIF, where a frame identical to the previous one lengthens its delay instead of
+/// being stored again. Viewers slow down delays under 20ms, so frames shown for less than that
+/// are replaced by the next one.
+struct Gif {
+    encoder: GifEncoder<BufWriter<File>>,
+    /// Frame waiting for its delay to be known, with the number of the frame it started at.
+    pending: Option<(RgbaImage, u64)>,
+    frames: u64,
+}
+
+impl Gif {
+    fn create(path: &str) -> ImageResult<Gif> {
+        let mut encoder = GifEncoder::new_with_speed(BufWriter::new(File::create(path)?), 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        Ok(Gif { encoder, pending: None, frames: 0 })
+    }
+
+    /// Time at the start of a frame, in the hundredths of a second GIF delays are stored in.
+    fn centiseconds(frame: u64) -> u64 {
+        (frame * 100 * CYCLES_PER_FRAME as u64 + CYCLES_PER_SECOND as u64 / 2) / CYCLES_PER_SECOND as u64
+    }
+
+    fn flush(&mut self) -> ImageResult<()> {
+        if let Some((image, start)) = self.pending.take() {
+            let delay = Self::centiseconds(self.frames) - Self::centiseconds(start);
+            self.encoder.encode_frame(Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(delay as u32 * 10, 1)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Output for Gif {
+    fn frame(&mut self, clip: Clip) -> ImageResult<()> {
+        match &mut self.pending {
+            Some((image, _)) if *image == clip.image => {}
+            Some((image, start)) if Self::centiseconds(self.frames) - Self::centiseconds(*start) < 2 => {
+                *image = clip.image;
+            }
+            _ => {
+                self.flush()?;
+                self.pending = Some((clip.image, self.frames));
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ImageResult<()> {
+        self.flush()
+    }
+}
+
+/// RIFF chunk, padded to an even size.
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn list(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    chunk(b"LIST", &[kind.as_slice(), data].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read, remove_file};
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("iron_boy_{}_{name}", std::process::id()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn avi_sizes_and_offsets() {
+        let path = temp_path("test.avi");
+        let mut avi = Avi::create(&path, 4, 2).unwrap();
+        avi.frame(&RgbaImage::new(4, 2)).unwrap();
+        avi.frame(&RgbaImage::from_pixel(4, 2, image::Rgba([1, 2, 3, 255]))).unwrap();
+        avi.finish().unwrap();
+        drop(avi);
+        let data = read(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        let frame_size = 4 * 2 * 3;
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+        // Total frames in the main header
+        assert_eq!(u32_at(&data, 48), 2);
+
+        let movi = Avi::HEADER_SIZE as usize - 4;
+        assert_eq!(&data[movi - 8..movi - 4], b"LIST");
+        assert_eq!(&data[movi..movi + 4], b"movi");
+        let movi_size = u32_at(&data, movi - 4) as usize;
+        assert_eq!(movi_size, 4 + 2 * (8 + frame_size));
+
+        let idx1 = movi + movi_size;
+        assert_eq!(&data[idx1..idx1 + 4], b"idx1");
+        assert_eq!(u32_at(&data, idx1 + 4), 2 * 16);
+        assert_eq!(idx1 + 8 + 2 * 16, data.len());
+        for frame in 0..2 {
+            let entry = idx1 + 8 + frame * 16;
+            assert_eq!(&data[entry..entry + 4], b"00db");
+            // Offsets point at the frame chunk, counting from the movi tag
+            let offset = movi + u32_at(&data, entry + 8) as usize;
+            assert_eq!(&data[offset..offset + 4], b"00db");
+            assert_eq!(u32_at(&data, offset + 4) as usize, frame_size);
+            assert_eq!(u32_at(&data, entry + 12) as usize, frame_size);
+        }
+        // Pixels are stored as BGR
+        let second = movi + 4 + 8 + frame_size + 8;
+        assert_eq!(&data[second..second + 3], [3, 2, 1]);
+    }
+
+    #[test]
+    fn wav_sizes_and_samples() {
+        let path = temp_path("test.wav");
+        let mut wav = Wav::create(&path, 44100).unwrap();
+        wav.samples(&[0.5, -0.5]).unwrap();
+        wav.samples(&[1.5, 0.0]).unwrap();
+        wav.finish().unwrap();
+        drop(wav);
+        let data = read(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 24), 44100);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 8);
+        assert_eq!(data.len(), 44 + 8);
+
+        let samples: Vec<i16> = data[44..]
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        assert_eq!(samples, [16383, -16383, i16::MAX, 0]);
+    }
+}
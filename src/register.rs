@@ -1,4 +1,5 @@
 use crate::mmu::MemoryManagementUnit;
+use crate::model::Model;
 use crate::register::RegisterId::{A, B, C, D, E, H, L};
 use crate::register::WordRegister::StackPointer;
 use std::ops::{Index, IndexMut};
@@ -59,26 +60,29 @@ pub enum ConditionCode {
 }
 
 impl Register {
-    pub fn new(boot_rom: bool) -> Self {
+    pub fn new(boot_rom: bool, model: Model) -> Self {
         if !boot_rom {
+            let [a, f, b, c, d, e, h, l] = model.registers();
+            let mut flags = FlagRegister {
+                z: false,
+                n: false,
+                h: false,
+                c: false,
+            };
+            flags.set(f);
             Self {
                 registers: vec![
-                    ByteRegister { value: 0x01, id: A },
-                    ByteRegister { value: 0x00, id: B },
-                    ByteRegister { value: 0x13, id: C },
-                    ByteRegister { value: 0x00, id: D },
-                    ByteRegister { value: 0xD8, id: E },
-                    ByteRegister { value: 0x01, id: H },
-                    ByteRegister { value: 0x4D, id: L },
+                    ByteRegister { value: a, id: A },
+                    ByteRegister { value: b, id: B },
+                    ByteRegister { value: c, id: C },
+                    ByteRegister { value: d, id: D },
+                    ByteRegister { value: e, id: E },
+                    ByteRegister { value: h, id: H },
+                    ByteRegister { value: l, id: L },
                 ],
                 pc: ProgramCounter(0x0100),
                 sp: StackPointer(0xFFFE),
-                flags: FlagRegister {
-                    z: true,
-                    n: false,
-                    h: true,
-                    c: true,
-                },
+                flags,
             }
         } else {
             Self {
@@ -1,11 +1,14 @@
 use pixels::Pixels;
 use std::cmp::max;
 use instant::{Duration, Instant};
+use crate::filter::{Buffer, FilterChain};
+use crate::{HEIGHT, WIDTH};
 
 #[derive(Default)]
 pub struct Renderer {
     pub(crate) slowest: Duration,
     pixels: Option<Pixels>,
+    pub(crate) filters: FilterChain,
 }
 
 impl Renderer {
@@ -13,6 +16,7 @@ impl Renderer {
         Self {
             slowest: Duration::from_secs(0),
             pixels: None,
+            filters: FilterChain::default(),
         }
     }
 
@@ -24,11 +28,35 @@ impl Renderer {
         self.pixels = Some(pixels);
     }
 
-    pub(crate) fn render(&mut self, screen: &[u8]) {
+    /// Shows a RGBA frame of the specified width, after running it through the filters. The
+    /// outlines, in pixels of the Game Boy screen found at `origin` in the frame, are drawn on a
+    /// copy so they don't end up in screenshots or recordings.
+    pub(crate) fn render(
+        &mut self,
+        frame: &[u8],
+        width: usize,
+        outlines: &[Outline],
+        origin: (usize, usize),
+    ) {
         let now = Instant::now();
-        if let Some(pixels) = self.pixels().as_mut() {
-            let frame = pixels.get_frame_mut();
-            frame.copy_from_slice(screen);
+        if let Some(pixels) = self.pixels.as_mut() {
+            let outlined;
+            let screen = if outlines.is_empty() {
+                frame
+            } else {
+                outlined = draw_outlines(frame, width, outlines, origin);
+                &outlined
+            };
+            if self.filters.is_empty() {
+                pixels.get_frame_mut().copy_from_slice(screen);
+            } else {
+                let filtered = self.filters.apply(Buffer::from_rgba(screen, width));
+                let rgba = filtered.to_rgba();
+                if pixels.get_frame_mut().len() != rgba.len() {
+                    pixels.resize_buffer(filtered.width as u32, filtered.height as u32).unwrap();
+                }
+                pixels.get_frame_mut().copy_from_slice(&rgba);
+            }
             pixels.render().unwrap();
             let duration = Instant::now() - now;
             // println!("Render took {:?}", duration);
@@ -36,3 +64,75 @@ impl Renderer {
         }
     }
 }
+
+/// Rectangle outlined on top of the Game Boy screen, such as the bounds of a sprite.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Outline {
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// RGBA color of the outlines.
+const OUTLINE: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+/// Copies the frame with the outlines drawn on it, leaving out the parts off the Game Boy screen.
+fn draw_outlines(
+    frame: &[u8],
+    width: usize,
+    outlines: &[Outline],
+    origin: (usize, usize),
+) -> Vec<u8> {
+    let mut outlined = frame.to_vec();
+    for outline in outlines {
+        let (right, bottom) = (outline.left + outline.width - 1, outline.top + outline.height - 1);
+        for y in outline.top..=bottom {
+            for x in outline.left..=right {
+                let edge = x == outline.left || x == right || y == outline.top || y == bottom;
+                if edge && (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y) {
+                    let i = ((origin.1 + y as usize) * width + origin.0 + x as usize) * 4;
+                    outlined[i..i + 4].copy_from_slice(&OUTLINE);
+                }
+            }
+        }
+    }
+    outlined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_edges_and_clips_to_screen() {
+        let frame = vec![0; WIDTH * HEIGHT * 4];
+        let outline = Outline { left: -4, top: 10, width: 8, height: 16 };
+        let outlined = draw_outlines(&frame, WIDTH, &[outline], (0, 0));
+        let pixel = |x: usize, y: usize| &outlined[(y * WIDTH + x) * 4..(y * WIDTH + x) * 4 + 4];
+
+        assert_eq!(pixel(0, 10), OUTLINE);
+        assert_eq!(pixel(3, 25), OUTLINE);
+        assert_eq!(pixel(3, 17), OUTLINE);
+        assert_eq!(pixel(0, 17), [0; 4]);
+        assert_eq!(pixel(4, 10), [0; 4]);
+        // The original frame is left as it was
+        assert!(frame.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn outlines_game_boy_screen_inside_larger_frame() {
+        let width = WIDTH + 20;
+        let frame = vec![0; width * (HEIGHT + 10) * 4];
+        let outline = Outline { left: 0, top: 0, width: 2, height: 2 };
+        let outlined = draw_outlines(&frame, width, &[outline], (20, 10));
+
+        let drawn: Vec<usize> = outlined
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, pixel)| *pixel == OUTLINE)
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(drawn, [10 * width + 20, 10 * width + 21, 11 * width + 20, 11 * width + 21]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::imageops::{resize, FilterType};
+use image::{ImageResult, RgbaImage};
+
+use crate::mmu::MemoryManagementUnit;
+use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+use crate::{HEIGHT, WIDTH};
+
+/// The last frame shown, as colored by the selected palette or the SGB, scaled up by an
+/// integer factor.
+pub fn capture(mmu: &MemoryManagementUnit, scale: u32) -> RgbaImage {
+    let image = match &mmu.sgb {
+        Some(sgb) => RgbaImage::from_raw(SGB_WIDTH as u32, SGB_HEIGHT as u32, sgb.frame.clone()),
+        None => RgbaImage::from_raw(WIDTH as u32, HEIGHT as u32, mmu.ppu.screen.to_vec()),
+    }
+    .unwrap();
+
+    let scale = scale.max(1);
+    if scale == 1 {
+        return image;
+    }
+    resize(&image, image.width() * scale, image.height() * scale, FilterType::Nearest)
+}
+
+/// Saves the last frame next to the ROM, named after the current time. Returns the file name.
+pub fn save(mmu: &MemoryManagementUnit, rom_path: &str, scale: u32) -> ImageResult<String> {
+    let path = format!("{rom_path}.{}.png", timestamp());
+    capture(mmu, scale).save(&path)?;
+    Ok(path)
+}
+
+/// Current UTC time as YYYYMMDD-HHMMSS-mmm.
+pub(crate) fn timestamp() -> String {
+    format_timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// Formats a time since the Unix epoch as YYYYMMDD-HHMMSS-mmm.
+fn format_timestamp(since_epoch: Duration) -> String {
+    let seconds = since_epoch.as_secs();
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Days since 1970-01-01 to a civil date, counting years from March so leap days come last
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}-{:03}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_civil_dates_with_leap_days() {
+        let cases = [
+            (0, "19700101-000000-000"),
+            (951_782_400, "20000229-000000-000"),
+            (951_868_799, "20000229-235959-000"),
+            (1_709_208_000, "20240229-120000-000"),
+            (1_709_251_200, "20240301-000000-000"),
+            (4_107_542_399, "21000228-235959-000"),
+            (4_107_542_400, "21000301-000000-000"),
+        ];
+        for (seconds, text) in cases {
+            assert_eq!(format_timestamp(Duration::from_secs(seconds)), text);
+        }
+        assert_eq!(format_timestamp(Duration::from_millis(1_234_567_890_123)), "20090213-233130-123");
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::filter::Filter;
+use crate::logger::Logger;
+
+/// Per-game settings, read from a JSON file next to the ROM. Options given on the command line
+/// take precedence over them.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Post-processing applied to every frame, in order.
+    pub filters: Vec<Filter>,
+    /// Sprites drawn per line, above the hardware limit of 10 to reduce flicker.
+    pub sprite_limit: Option<u8>,
+}
+
+impl Settings {
+    /// Loads the settings file with the same name as the ROM, if there is one. An invalid file is
+    /// reported and ignored.
+    pub fn for_rom(rom_path: &Path) -> Settings {
+        let path = Self::path_for_rom(rom_path);
+        let Ok(contents) = read_to_string(&path) else {
+            return Settings::default();
+        };
+        match serde_json::from_str(&contents) {
+            Ok(settings) => {
+                Logger::info(format!("Loaded settings from {}", path.display()));
+                settings
+            }
+            Err(e) => {
+                Logger::error(format!("Ignoring invalid settings file {}: {e}", path.display()));
+                Settings::default()
+            }
+        }
+    }
+
+    fn path_for_rom(rom_path: &Path) -> PathBuf {
+        // Save files are named after the ROM they belong to, e.g. game.gb.sav.bin
+        let rom_path = rom_path.to_string_lossy();
+        let rom_path = rom_path
+            .trim_end_matches(".sav.bin")
+            .trim_end_matches(".sav.json");
+        Path::new(rom_path).with_extension("settings.json")
+    }
+}
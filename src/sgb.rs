@@ -0,0 +1,539 @@
+use std::cmp::Ordering;
+use std::mem::replace;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mmu::MemoryArea;
+use crate::ppu::{IndexedPixel, PixelProcessingUnit};
+use crate::{HEIGHT, WIDTH};
+
+/// Size of the SGB output, with the Game Boy screen framed by the border.
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+/// Position of the Game Boy screen in the SGB output.
+pub const SCREEN_X: usize = (SGB_WIDTH - WIDTH) / 2;
+pub const SCREEN_Y: usize = (SGB_HEIGHT - HEIGHT) / 2;
+
+/// The screen is split into 8x8 cells, each colored with one of the 4 palettes.
+const CELLS_X: usize = WIDTH / 8;
+const CELLS_Y: usize = HEIGHT / 8;
+
+const PACKET_SIZE: usize = 16;
+/// Bytes copied from VRAM by the transfer commands.
+const TRANSFER_SIZE: usize = 0x1000;
+/// 90 bytes of 4 palettes each, one per cell.
+const ATTRIBUTE_FILE_SIZE: usize = CELLS_X * CELLS_Y / 4;
+const ATTRIBUTE_FILES: usize = 45;
+/// 32x32 tile map followed by palettes 4-7 of 16 colors each.
+const BORDER_MAP_SIZE: usize = 0x880;
+
+/// Palette used until the game sets its own, as RGB555.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+fn init_frame() -> Vec<u8> {
+    vec![0; SGB_WIDTH * SGB_HEIGHT * 4]
+}
+
+fn init_screen() -> Vec<u16> {
+    vec![0; WIDTH * HEIGHT]
+}
+
+/// Data the SGB copies from VRAM on the next frame.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+enum Transfer {
+    /// PAL_TRN: 512 system palettes
+    Palettes,
+    /// CHR_TRN: Either half of the 256 border tiles
+    BorderTiles(usize),
+    /// PCT_TRN: Border tile map and palettes
+    BorderMap,
+    /// ATTR_TRN: 45 attribute files
+    AttributeFiles,
+}
+
+/// Super Game Boy, receiving command packets through P1 to color the screen and draw a border
+/// around it.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Sgb {
+    /// P14 and P15 as last written to P1.
+    lines: u8,
+    /// A reset pulse started a packet, which is sent one bit per pulse on P14 or P15.
+    receiving: bool,
+    bits: usize,
+    packet: Vec<u8>,
+    /// Packets received so far of a command longer than one packet.
+    command: Vec<u8>,
+    /// Joypads enabled through MLT_REQ, and the one P1 currently reads.
+    players: u8,
+    player: u8,
+    /// Palettes 0-3 coloring the screen, as RGB555.
+    palettes: [[u16; 4]; 4],
+    /// System palettes loaded through PAL_TRN and selected with PAL_SET.
+    system_palettes: Vec<[u16; 4]>,
+    /// Palette used by each cell of the screen.
+    attributes: Vec<u8>,
+    /// Attribute files loaded through ATTR_TRN and selected with ATTR_SET.
+    attribute_files: Vec<u8>,
+    /// MASK_EN: 0 shows the screen, 1 freezes it, 2 blanks it to black and 3 to color 0.
+    mask: u8,
+    /// 4bpp border tiles loaded through CHR_TRN.
+    border_tiles: Vec<u8>,
+    /// Border tile map and palettes loaded through PCT_TRN.
+    border_map: Vec<u8>,
+    transfer: Option<Transfer>,
+    /// Colored Game Boy screen, kept around while frozen.
+    #[serde(skip, default = "init_screen")]
+    screen: Vec<u16>,
+    /// Output frame with the border.
+    #[serde(skip, default = "init_frame")]
+    pub frame: Vec<u8>,
+}
+
+impl MemoryArea for Sgb {
+    fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            // Deselecting both button lines reads the joypad ID
+            0xFF00 if self.players > 1 && self.lines == 0x30 => Some(0xF0 | (0x0F - self.player)),
+            // Only the first joypad has buttons being pressed
+            0xFF00 if self.player != 0 => Some(0xC0 | self.lines | 0x0F),
+            _ => None,
+        }
+    }
+
+    /// Listens to P1 writes, which still reach the joypad.
+    fn write(&mut self, address: usize, value: u8) -> bool {
+        if address == 0xFF00 {
+            self.joypad_write(value);
+        }
+        false
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Self {
+            lines: 0x30,
+            receiving: false,
+            bits: 0,
+            packet: vec![0; PACKET_SIZE],
+            command: vec![],
+            players: 1,
+            player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; 0x200],
+            attributes: vec![0; CELLS_X * CELLS_Y],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            mask: 0,
+            border_tiles: vec![0; TRANSFER_SIZE * 2],
+            border_map: vec![0; BORDER_MAP_SIZE],
+            transfer: None,
+            screen: init_screen(),
+            frame: init_frame(),
+        }
+    }
+
+    fn joypad_write(&mut self, value: u8) {
+        let lines = value & 0x30;
+        let previous = replace(&mut self.lines, lines);
+
+        match lines {
+            0x00 => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet.fill(0);
+            }
+            // P15 low sends a 1 and P14 low a 0, both lines go high again between bits
+            0x10 | 0x20 if self.receiving && previous == 0x30 => self.receive_bit(lines == 0x10),
+            // Raising P15 after reading the buttons moves on to the next joypad
+            0x30 if !self.receiving && self.players > 1 && previous & 0x20 == 0 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        if self.bits == PACKET_SIZE * 8 {
+            // A 0 stop bit ends the packet
+            self.receiving = false;
+            if !bit {
+                self.receive_packet();
+            }
+            return;
+        }
+
+        self.packet[self.bits / 8] |= (bit as u8) << (self.bits % 8);
+        self.bits += 1;
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            0x00 => self.set_palettes(0, 1, data),
+            0x01 => self.set_palettes(2, 3, data),
+            0x02 => self.set_palettes(0, 3, data),
+            0x03 => self.set_palettes(1, 2, data),
+            0x04 => self.attribute_blocks(data),
+            0x05 => self.attribute_lines(data),
+            0x06 => self.attribute_division(data),
+            0x07 => self.attribute_characters(data),
+            0x0A => self.set_system_palettes(data),
+            0x0B => self.transfer = Some(Transfer::Palettes),
+            0x11 => {
+                self.players = match data[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            0x13 => self.transfer = Some(Transfer::BorderTiles((data[1] & 0x01) as usize)),
+            0x14 => self.transfer = Some(Transfer::BorderMap),
+            0x15 => self.transfer = Some(Transfer::AttributeFiles),
+            0x16 => self.set_attribute_file(data[1]),
+            0x17 => self.mask = data[1] & 0x03,
+            // Sound and SNES program commands need the SNES side, which isn't emulated
+            _ => {}
+        }
+    }
+
+    fn color(data: &[u8], index: usize) -> u16 {
+        u16::from_le_bytes([data[index], data[index + 1]])
+    }
+
+    /// PAL01, PAL23, PAL03 and PAL12: Colors 1-3 of two palettes, and color 0 shared by all.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = Self::color(data, 1);
+        }
+        for color in 1..4 {
+            self.palettes[first][color] = Self::color(data, 1 + color * 2);
+            self.palettes[second][color] = Self::color(data, 7 + color * 2);
+        }
+    }
+
+    /// PAL_SET: Copies four system palettes, optionally applying an attribute file.
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for (i, palette) in self.palettes.iter_mut().enumerate() {
+            let id = Self::color(data, 1 + i * 2) as usize & 0x1FF;
+            *palette = self.system_palettes[id];
+        }
+        let backdrop = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = backdrop;
+        }
+
+        if data[9] & 0x80 != 0 {
+            self.set_attribute_file(data[9]);
+        } else if data[9] & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    /// ATTR_SET: Colors the screen with an attribute file, bit 6 also cancels the mask.
+    fn set_attribute_file(&mut self, value: u8) {
+        let file = (value & 0x3F) as usize;
+        if file < ATTRIBUTE_FILES {
+            let start = file * ATTRIBUTE_FILE_SIZE;
+            let palettes = self.attribute_files[start..start + ATTRIBUTE_FILE_SIZE]
+                .iter()
+                .flat_map(|byte| (0..4).rev().map(move |i| (byte >> (i * 2)) & 0x03));
+            for (attribute, palette) in self.attributes.iter_mut().zip(palettes) {
+                *attribute = palette;
+            }
+        }
+        if value & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        self.attributes[y * CELLS_X + x] = palette;
+    }
+
+    /// ATTR_BLK: Colors the inside, border and outside of rectangles.
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let [inside, border, outside] = [0, 2, 4].map(|shift| (set[1] >> shift) & 0x03);
+            // Changing only the inside or outside also changes the border
+            let (control, border) = match set[0] & 0x07 {
+                0x01 => (0x03, inside),
+                0x04 => (0x06, outside),
+                control => (control, border),
+            };
+            let [x1, y1, x2, y2] = [set[2], set[3], set[4], set[5]].map(|c| (c & 0x1F) as usize);
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let (flag, palette) = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        (0x01, inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        (0x04, outside)
+                    } else {
+                        (0x02, border)
+                    };
+                    if control & flag != 0 {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    /// ATTR_LIN: Colors whole rows or columns.
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let lines = data[1] as usize;
+        for &line in data[2..].iter().take(lines) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+            if line & 0x80 != 0 && index < CELLS_Y {
+                (0..CELLS_X).for_each(|x| self.set_attribute(x, index, palette));
+            } else if line & 0x80 == 0 && index < CELLS_X {
+                (0..CELLS_Y).for_each(|y| self.set_attribute(index, y, palette));
+            }
+        }
+    }
+
+    /// ATTR_DIV: Splits the screen in two along a row or column, which gets its own palette.
+    fn attribute_division(&mut self, data: &[u8]) {
+        let [after, before, on] = [0, 2, 4].map(|shift| (data[1] >> shift) & 0x03);
+        let horizontal = data[1] & 0x40 != 0;
+        let at = (data[2] & 0x1F) as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&at) {
+                    Ordering::Less => before,
+                    Ordering::Equal => on,
+                    Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    /// ATTR_CHR: Colors consecutive cells, left to right or top to bottom.
+    fn attribute_characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = ((data[1] & 0x1F) as usize, (data[2] & 0x1F) as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+        let palettes = data[6..]
+            .iter()
+            .flat_map(|byte| (0..4).rev().map(move |i| (byte >> (i * 2)) & 0x03));
+
+        for palette in palettes.take(count) {
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+            self.set_attribute(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    /// Transfers read the tiles shown on screen, in the order the background map lists them.
+    /// Games show 256 consecutive tiles with an identity BGP while a transfer runs.
+    fn vram_data(ppu: &PixelProcessingUnit) -> Vec<u8> {
+        let map = if ppu.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+        (0..TRANSFER_SIZE / 16)
+            .flat_map(|tile| {
+                let index = ppu.vram[map + (tile / CELLS_X) * 32 + tile % CELLS_X];
+                let address = if ppu.lcdc & 0x10 != 0 {
+                    index as usize * 16
+                } else {
+                    (0x1000 + index as i8 as isize * 16) as usize
+                };
+                ppu.vram[address..address + 16].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Finishes any pending transfer and draws the frame the PPU just completed.
+    pub fn frame_done(&mut self, ppu: &PixelProcessingUnit) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = Self::vram_data(ppu);
+            match transfer {
+                Transfer::Palettes => {
+                    self.system_palettes = data
+                        .chunks_exact(8)
+                        .map(|colors| [0, 1, 2, 3].map(|i| Self::color(colors, i * 2)))
+                        .collect();
+                }
+                Transfer::BorderTiles(half) => {
+                    self.border_tiles[half * TRANSFER_SIZE..][..TRANSFER_SIZE].copy_from_slice(&data)
+                }
+                Transfer::BorderMap => self.border_map.copy_from_slice(&data[..BORDER_MAP_SIZE]),
+                Transfer::AttributeFiles => {
+                    let size = ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE;
+                    self.attribute_files.copy_from_slice(&data[..size])
+                }
+            }
+        }
+
+        self.render(&ppu.indexed);
+    }
+
+    fn render(&mut self, pixels: &[IndexedPixel]) {
+        match self.mask {
+            0 => {
+                for (i, pixel) in pixels.iter().enumerate() {
+                    let cell = (i / WIDTH / 8) * CELLS_X + (i % WIDTH) / 8;
+                    let palette = self.attributes[cell] as usize;
+                    self.screen[i] = self.palettes[palette][pixel.shade() as usize];
+                }
+            }
+            0x01 => {}
+            0x02 => self.screen.fill(0x0000),
+            _ => self.screen.fill(self.palettes[0][0]),
+        }
+
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let on_screen = (SCREEN_X..SCREEN_X + WIDTH).contains(&x)
+                    && (SCREEN_Y..SCREEN_Y + HEIGHT).contains(&y);
+                // The border is drawn above the screen, which shows through color 0
+                let color = match self.border_color(x, y) {
+                    Some(color) => color,
+                    None if on_screen => self.screen[(y - SCREEN_Y) * WIDTH + x - SCREEN_X],
+                    None => self.palettes[0][0],
+                };
+                let i = (y * SGB_WIDTH + x) * 4;
+                self.frame[i..i + 4].copy_from_slice(&Self::rgba(color));
+            }
+        }
+    }
+
+    fn border_color(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = Self::color(&self.border_map, ((y / 8) * 32 + x / 8) * 2);
+        let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..];
+        let palette = ((entry >> 10) & 0x03) as usize;
+        let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let shift = if entry & 0x4000 != 0 { x % 8 } else { 7 - x % 8 };
+
+        // Bitplanes 0 and 1 are interleaved in the first half of the tile, 2 and 3 in the second
+        let planes = [tile[row * 2], tile[row * 2 + 1], tile[16 + row * 2], tile[17 + row * 2]];
+        let color = planes
+            .iter()
+            .enumerate()
+            .fold(0, |color, (plane, byte)| color | ((byte >> shift) & 1) << plane) as usize;
+
+        (color != 0).then(|| Self::color(&self.border_map, 0x800 + palette * 32 + color * 2))
+    }
+
+    fn rgba(color: u16) -> [u8; 4] {
+        let channel = |shift: u16| {
+            let c = ((color >> shift) & 0x1F) as u8;
+            (c << 3) | (c >> 2)
+        };
+        [channel(0), channel(5), channel(10), 255]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a packet one bit at a time through P1, the way games do.
+    fn send(sgb: &mut Sgb, bytes: &[u8]) {
+        let mut packet = [0; PACKET_SIZE];
+        packet[..bytes.len()].copy_from_slice(bytes);
+
+        sgb.write(0xFF00, 0x00);
+        sgb.write(0xFF00, 0x30);
+        for bit in 0..PACKET_SIZE * 8 {
+            let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+            sgb.write(0xFF00, if one { 0x10 } else { 0x20 });
+            sgb.write(0xFF00, 0x30);
+        }
+        // Stop bit
+        sgb.write(0xFF00, 0x20);
+        sgb.write(0xFF00, 0x30);
+    }
+
+    fn attribute(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attributes[y * CELLS_X + x]
+    }
+
+    #[test]
+    fn pal01_sets_two_palettes_and_shared_color_0() {
+        let mut sgb = Sgb::new();
+        #[rustfmt::skip]
+        send(&mut sgb, &[
+            0x01,
+            0xFF, 0x7F, 0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C,
+            0x11, 0x11, 0x22, 0x22, 0x33, 0x33,
+        ]);
+
+        assert_eq!(sgb.palettes[0], [0x7FFF, 0x001F, 0x03E0, 0x7C00]);
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x1111, 0x2222, 0x3333]);
+        assert_eq!(sgb.palettes[2][0], 0x7FFF);
+        assert_eq!(sgb.palettes[2][1..], DEFAULT_PALETTE[1..]);
+    }
+
+    #[test]
+    fn attr_blk_waits_for_all_packets() {
+        let mut sgb = Sgb::new();
+        // The third block starts at the end of the first packet
+        #[rustfmt::skip]
+        send(&mut sgb, &[
+            0x22, 3,
+            0x01, 0x01, 1, 1, 3, 3,
+            0x02, 0x08, 10, 10, 12, 12,
+            0x01, 0x03,
+        ]);
+        assert_eq!(attribute(&sgb, 2, 2), 0);
+
+        send(&mut sgb, &[15, 0, 19, 4]);
+        // Changing only the inside also colors the border
+        assert_eq!(attribute(&sgb, 2, 2), 1);
+        assert_eq!(attribute(&sgb, 1, 1), 1);
+        assert_eq!(attribute(&sgb, 0, 0), 0);
+        assert_eq!(attribute(&sgb, 10, 10), 2);
+        assert_eq!(attribute(&sgb, 11, 11), 0);
+        assert_eq!(attribute(&sgb, 15, 0), 3);
+        assert_eq!(attribute(&sgb, 19, 4), 3);
+        assert_eq!(attribute(&sgb, 14, 0), 0);
+        assert!(sgb.command.is_empty());
+    }
+
+    #[test]
+    fn mlt_req_cycles_through_joypads() {
+        let mut sgb = Sgb::new();
+        assert_eq!(sgb.read(0xFF00), None);
+
+        send(&mut sgb, &[0x89, 0x01]);
+        assert_eq!(sgb.read(0xFF00), Some(0xFF));
+        // Reading the buttons and raising P15 moves on to the second joypad
+        sgb.write(0xFF00, 0x10);
+        sgb.write(0xFF00, 0x30);
+        assert_eq!(sgb.read(0xFF00), Some(0xFE));
+        sgb.write(0xFF00, 0x10);
+        sgb.write(0xFF00, 0x30);
+        assert_eq!(sgb.read(0xFF00), Some(0xFF));
+
+        send(&mut sgb, &[0x89, 0x00]);
+        assert_eq!(sgb.players, 1);
+        assert_eq!(sgb.read(0xFF00), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::logger::Logger;
+
+/// Labels loaded from an RGBDS or no$gmb symbol file, using `bank:address label` entries.
+#[derive(Default, Debug)]
+pub struct Symbols {
+    labels: BTreeMap<(u16, u16), String>,
+    addresses: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    pub fn parse(contents: &str) -> Self {
+        let mut symbols = Self::default();
+
+        for line in contents.lines() {
+            // Both formats use ';' for comments, no$gmb also uses [section] headers
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+
+            let Some((location, label)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let (bank, address) = location.split_once(':').unwrap_or(("0", location));
+            let (Ok(bank), Ok(address)) = (
+                u16::from_str_radix(bank, 16),
+                u16::from_str_radix(address, 16),
+            ) else {
+                continue;
+            };
+
+            let label = label.trim().to_string();
+            symbols.addresses.entry(label.clone()).or_insert((bank, address));
+            symbols.labels.entry((bank, address)).or_insert(label);
+        }
+
+        symbols
+    }
+
+    /// Loads the symbol file with the same name as the ROM, if there is one.
+    pub fn for_rom(rom_path: &Path) -> Option<Self> {
+        let path = Self::path_for_rom(rom_path);
+        let contents = read_to_string(&path).ok()?;
+        let symbols = Self::parse(&contents);
+        Logger::info(format!(
+            "Loaded {} symbols from {}",
+            symbols.labels.len(),
+            path.display()
+        ));
+        Some(symbols)
+    }
+
+    fn path_for_rom(rom_path: &Path) -> PathBuf {
+        // Save files are named after the ROM they belong to, e.g. game.gb.sav.bin
+        let rom_path = rom_path.to_string_lossy();
+        let rom_path = rom_path
+            .trim_end_matches(".sav.bin")
+            .trim_end_matches(".sav.json");
+        Path::new(rom_path).with_extension("sym")
+    }
+
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        self.labels
+            .get(&(bank, address))
+            .or_else(|| self.labels.get(&(0, address)))
+            .map(String::as_str)
+    }
+
+    pub fn address(&self, label: &str) -> Option<(u16, u16)> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Finds the closest label at or before the given address, within the same memory region.
+    /// Labels without a bank are used when the bank has none.
+    pub fn nearest(&self, bank: u16, address: u16) -> Option<(&str, u16)> {
+        let region = address & 0xC000;
+        let nearest = |bank| {
+            self.labels
+                .range((bank, region)..=(bank, address))
+                .next_back()
+                .map(|(&(_, start), label)| (label.as_str(), address - start))
+        };
+        nearest(bank).or_else(|| nearest(0))
+    }
+
+    /// Formats an address as `bank:address`, followed by the closest label if there is one.
+    pub fn describe(symbols: Option<&Self>, bank: u16, address: u16) -> String {
+        match symbols.and_then(|symbols| symbols.nearest(bank, address)) {
+            Some((label, 0)) => format!("{bank:02X}:{address:04X} ({label})"),
+            Some((label, offset)) => format!("{bank:02X}:{address:04X} ({label}+{offset:#X})"),
+            None => format!("{bank:02X}:{address:04X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MemoryManagementUnit;
+    use crate::model::Model;
+
+    const SYMBOLS: &str = "\
+; File generated by rgblink
+[labels]
+00:0150 Start ; entry point
+00:0160 Start.loop
+01:4000 BankedCode
+02:4000 OtherBank
+C000 wBuffer
+01:4010 BankedCode
+";
+
+    #[test]
+    fn parses_labels_with_and_without_bank() {
+        let symbols = Symbols::parse(SYMBOLS);
+        assert_eq!(symbols.label(0, 0x0150), Some("Start"));
+        assert_eq!(symbols.label(1, 0x4000), Some("BankedCode"));
+        assert_eq!(symbols.label(2, 0x4000), Some("OtherBank"));
+        assert_eq!(symbols.address("wBuffer"), Some((0, 0xC000)));
+        // Bank 0 labels are found from any bank
+        assert_eq!(symbols.label(3, 0xC000), Some("wBuffer"));
+        // The first address of a label is kept
+        assert_eq!(symbols.address("BankedCode"), Some((1, 0x4000)));
+    }
+
+    #[test]
+    fn skips_comments_and_malformed_lines() {
+        let symbols = Symbols::parse(
+            "; 00:0100 Comment\nStart\n00:0150\nzz:0150 Bad\n00:xyz Bad\n[section]\n01:4000 Ok",
+        );
+        assert_eq!(symbols.labels.len(), 1);
+        assert_eq!(symbols.label(1, 0x4000), Some("Ok"));
+        assert_eq!(symbols.address("Bad"), None);
+    }
+
+    #[test]
+    fn nearest_stays_in_bank_and_region() {
+        let symbols = Symbols::parse(SYMBOLS);
+        assert_eq!(symbols.nearest(0, 0x0150), Some(("Start", 0)));
+        assert_eq!(symbols.nearest(0, 0x015F), Some(("Start", 0xF)));
+        assert_eq!(symbols.nearest(0, 0x0200), Some(("Start.loop", 0xA0)));
+        assert_eq!(symbols.nearest(2, 0x4020), Some(("OtherBank", 0x20)));
+        // Labels before the start of the region don't count
+        assert_eq!(symbols.nearest(0, 0x0100), None);
+        assert_eq!(symbols.nearest(0, 0x4000), None);
+        assert_eq!(symbols.nearest(3, 0x4000), None);
+    }
+
+    #[test]
+    fn describes_addresses() {
+        let symbols = Symbols::parse(SYMBOLS);
+        assert_eq!(Symbols::describe(Some(&symbols), 0, 0x0150), "00:0150 (Start)");
+        assert_eq!(Symbols::describe(Some(&symbols), 1, 0x4004), "01:4004 (BankedCode+0x4)");
+        assert_eq!(Symbols::describe(Some(&symbols), 0, 0x0100), "00:0100");
+        assert_eq!(Symbols::describe(None, 0, 0x0150), "00:0150");
+    }
+
+    #[test]
+    fn resolves_wram_labels_in_bank_selected_by_svbk() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        let mut mmu = MemoryManagementUnit::for_rom(rom, Model::Cgb);
+        mmu.symbols = Some(Symbols::parse("02:D000 wBank2\n03:D000 wBank3\n00:C000 wBuffer"));
+        mmu.write(0x70u8, 0x03u8);
+
+        assert_eq!(mmu.bank(0xD000), 3);
+        assert_eq!(mmu.describe_address(0xD004), "03:D004 (wBank3+0x4)");
+        assert_eq!(mmu.label_address("wBank3"), Some(0xD000));
+        assert_eq!(mmu.label_address("wBank2"), None);
+        assert_eq!(mmu.label_address("wBuffer"), Some(0xC000));
+    }
+}
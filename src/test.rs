@@ -1,32 +1,149 @@
 use std::ffi::OsStr;
 use std::fs::{read, read_dir};
 use std::io::Error;
-use std::panic;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
 use std::thread;
 
-use image::RgbaImage;
-
 use crate::cartridge::Cartridge;
-use crate::{run_frame, Gameboy, MemoryManagementUnit, HEIGHT, WIDTH};
+use crate::{run_frame, screenshot, Gameboy, MemoryManagementUnit, SaveFile};
+use crate::gameboy::SAVE_STATE_VERSION;
 use crate::logger::Logger;
+use crate::model::Model;
+use crate::ppu::RenderMode;
+use crate::symbols::Symbols;
 
 #[test]
 fn test_roms() -> Result<(), Error> {
-    let (test_status_tx, test_status_rv) = channel();
+    const TEST_DURATION: usize = 1200; // in frames
+
+    let failures = failing_roms(|rom| {
+        let rom_filename = osstr_to_str(rom.file_name());
+        Logger::info(format!("Testing {}", rom_filename));
+        let gameboy = run_rom(rom, RenderMode::Fifo, TEST_DURATION);
+
+        Logger::info(format!("Saving screenshot for {rom_filename}"));
+        let screenshot = screenshot::capture(&gameboy.mmu, 1);
+        screenshot
+            .save(Path::new(&format!("test_output/{}.png", rom_filename)))
+            .unwrap();
+
+        // ROMs without a reference image only have to run without panicking
+        match image::open(format!("test_ok/{rom_filename}.png")) {
+            Ok(expected) => {
+                screenshot == expected.to_rgba8() || KNOWN_FAILURES.contains(&rom_filename.as_str())
+            }
+            Err(_) => true,
+        }
+    })?;
+    assert!(failures.is_empty(), "Screenshots differ from test_ok on {failures:?}");
+    Ok(())
+}
+
+/// ROMs whose screenshot doesn't match their reference image in test_ok yet.
+const KNOWN_FAILURES: [&str; 3] = [
+    // CGB test, which hangs on STOP on the DMG
+    "interrupt_time.gb",
+    // Prints F as F0 instead of 00, which the test doesn't assert
+    "pop_timing.gb",
+    // MBC5 RAM enabling isn't emulated, failing the SRAM check at $A000
+    "sources-GS.gb",
+];
+
+#[test]
+fn test_scanline_renderer() -> Result<(), Error> {
+    // Drawing each line at once has to give the same frames as the pixel FIFO
+    const TEST_DURATION: usize = 600; // in frames
 
-    panic::set_hook(Box::new(|_info| std::process::exit(1)));
+    let mismatches = failing_roms(|rom| {
+        let fifo = run_rom(rom, RenderMode::Fifo, TEST_DURATION);
+        let scanline = run_rom(rom, RenderMode::Scanline, TEST_DURATION);
+        fifo.mmu.ppu.screen == scanline.mmu.ppu.screen
+    })?;
+    assert!(mismatches.is_empty(), "Scanline renderer differs from the pixel FIFO on {mismatches:?}");
+    Ok(())
+}
+
+/// Runs a check on every test ROM, each in a thread named after the ROM so that panics tell
+/// which one they came from. Returns the ROMs the check failed or panicked on.
+fn failing_roms(check: fn(&Path) -> bool) -> Result<Vec<String>, Error> {
+    let threads = test_rom_files()?
+        .into_iter()
+        .map(|rom| {
+            let name = osstr_to_str(rom.file_name());
+            let thread = thread::Builder::new().name(name.clone()).spawn(move || check(&rom))?;
+            Ok((name, thread))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(threads
+        .into_iter()
+        .filter_map(|(name, thread)| match thread.join() {
+            Ok(true) => None,
+            _ => Some(name),
+        })
+        .collect())
+}
+
+#[test]
+fn test_save_state_version() -> Result<(), Error> {
+    let rom = test_rom_files()?.into_iter().next().unwrap();
+    let mut gameboy = run_rom(&rom, RenderMode::Fifo, 1);
+
+    for format in SaveFile::FORMATS {
+        gameboy.version = SAVE_STATE_VERSION;
+        let save = format.save(&gameboy);
+        assert!(format.load(&save).is_ok(), "{format:?} state doesn't load");
+
+        gameboy.version = SAVE_STATE_VERSION + 1;
+        let newer = format.load(&format.save(&gameboy)).err().unwrap();
+        assert!(newer.starts_with("Unsupported save state version"), "{newer}");
+    }
 
+    // States made before the version field start with the registers
+    gameboy.version = SAVE_STATE_VERSION;
+    let old = SaveFile::Bin.save(&gameboy)[4..].to_vec();
+    assert!(SaveFile::Bin.load(&old).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_breakpoint_by_label() {
+    let mut rom = vec![0; 0x8000];
+    // jp Start, where Start loops on itself through a nop
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x154].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    let mut mmu = MemoryManagementUnit::for_rom(rom, Model::Dmg);
+    mmu.symbols = Some(Symbols::parse("00:0150 Start"));
+    let mut gameboy = Gameboy::new(mmu);
+    gameboy.breakpoints = vec!["label:Start".parse().unwrap()];
+
+    let mut cycles = 0;
+    while !gameboy.break_hit {
+        cycles += gameboy.cycle() as usize;
+        assert!(cycles < 100, "Breakpoint not reached");
+    }
+    assert_eq!(gameboy.reg.pc.value(), 0x0150);
+
+    // Resuming runs the instruction at the breakpoint, and the loop stops there again
+    gameboy.break_hit = false;
+    assert_ne!(gameboy.cycle(), 0);
+    assert_eq!(gameboy.reg.pc.value(), 0x0151);
+    while !gameboy.break_hit {
+        gameboy.cycle();
+    }
+    assert_eq!(gameboy.reg.pc.value(), 0x0150);
+}
+
+fn test_rom_files() -> Result<Vec<PathBuf>, Error> {
     let all_tests = read_dir("test_rom")?;
-    let all_tests: Vec<PathBuf> = all_tests
+    Ok(all_tests
         .filter_map(|entry| {
             let output = match entry {
                 Ok(entry) => match entry.metadata().map(|entry| entry.is_file()) {
                     Ok(true) => {
                         let path = entry.path();
                         match path.extension() {
-                            Some(ext) if ext.to_ascii_lowercase() == "gb" => Ok(path),
+                            Some(ext) if ext.eq_ignore_ascii_case("gb") || ext.eq_ignore_ascii_case("gbc") => Ok(path),
                             Some(_) => Err(path),
                             None => Err(path),
                         }
@@ -44,52 +161,52 @@ fn test_roms() -> Result<(), Error> {
                 }
             }
         })
-        .collect();
-
-    let total = all_tests.len();
-    for (idx, rom) in all_tests.into_iter().enumerate() {
-        let rom_filename = osstr_to_str(rom.file_name());
-        let rom_output_png = format!("test_output/{}.png", rom_filename);
-
-        let tx_finish = test_status_tx.clone();
-        thread::spawn(move || {
-            const TEST_DURATION: usize = 1200; // in frames
-
-            Logger::info(format!("Testing {}", rom_filename));
-            let rom_vec = read(rom.clone()).unwrap();
-            let cartridge = Cartridge::new(&rom_vec);
-
-            let mem = MemoryManagementUnit::new(rom_vec, cartridge, None, Path::new(&rom));
-            let mut gameboy = Gameboy::new(mem);
+        .collect())
+}
 
-            for _frame in 0..TEST_DURATION {
-                run_frame(&mut gameboy, false, None, None);
-            }
+fn run_rom(rom: &Path, render_mode: RenderMode, frames: usize) -> Gameboy {
+    let rom_vec = read(rom).unwrap();
+    let cartridge = Cartridge::new(&rom_vec);
 
-            Logger::info(format!("Saving screenshot for {rom_filename}"));
+    let mem = MemoryManagementUnit::new(rom_vec, cartridge, None, rom_model(rom), rom, render_mode).unwrap();
+    let mut gameboy = Gameboy::new(mem);
 
-            RgbaImage::from_raw(WIDTH as u32, HEIGHT as u32, gameboy.mmu.ppu.screen.to_vec())
-                .unwrap()
-                .save(Path::new(&rom_output_png))
-                .unwrap();
+    for _frame in 0..frames {
+        run_frame(&mut gameboy, false, &mut false, None, None);
+    }
+    gameboy
+}
 
-            tx_finish.send(idx).unwrap();
-        });
+/// The model a test ROM is made for, from the Mooneye suffix of its name: either models like
+/// `-dmgABCmgb` or families like `-GS`. The first one listed is picked, and `.gbc` ROMs
+/// without a suffix run on the CGB.
+fn rom_model(rom: &Path) -> Model {
+    let default = match rom.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("gbc") => Model::Cgb,
+        _ => Model::default(),
+    };
+    let name = osstr_to_str(rom.file_stem());
+    let suffix = match name.rsplit_once('-') {
+        Some((_, suffix)) => suffix,
+        None => return default,
+    };
+    if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_uppercase()) {
+        return match suffix.as_bytes()[0] {
+            b'S' => Model::Sgb,
+            b'C' => Model::Cgb,
+            b'A' => Model::Agb,
+            _ => Model::Dmg,
+        };
     }
-    let mut count = 0;
-    while count < total {
-        match test_status_rv.recv() {
-            Ok(_) => {
-                count += 1;
-                Logger::info(format!("Finished test {count}/{total}"));
-            }
-            Err(e) => Logger::error(format!("Error executing test: {e}")),
-        }
-        if count == total {
-            return Ok(());
-        }
+    match suffix {
+        _ if suffix.starts_with("dmg0") => Model::Dmg0,
+        _ if suffix.starts_with("mgb") => Model::Mgb,
+        _ if suffix.starts_with("sgb2") => Model::Sgb2,
+        _ if suffix.starts_with("sgb") => Model::Sgb,
+        _ if suffix.starts_with("cgb") => Model::Cgb,
+        _ if suffix.starts_with("agb") => Model::Agb,
+        _ => default,
     }
-    Err(Error::last_os_error())
 }
 
 #[inline]
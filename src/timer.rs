@@ -1,4 +1,5 @@
 use crate::mmu::MemoryArea;
+use crate::model::Model;
 
 use serde::{Deserialize, Serialize};
 
@@ -25,11 +26,7 @@ impl MemoryArea for Timer {
 
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
-            Timer::DIVIDER => {
-                let old_ticks = self.ticks;
-                self.ticks = 0x00;
-                self.tima_increase(old_ticks);
-            }
+            Timer::DIVIDER => self.reset_divider(),
             Timer::TIMA => {
                 if !self.interrupt_served {
                     self.tima = value;
@@ -55,12 +52,12 @@ impl Timer {
     const TMA: usize = 0xFF06;
     const TAC: usize = 0xFF07;
 
-    pub fn new(boot_rom: bool) -> Self {
+    pub fn new(boot_rom: bool, model: Model) -> Self {
         Self {
             tima: 0,
             tma: 0,
             tac: 0,
-            ticks: if boot_rom { 0x00 } else { 0xABCC },
+            ticks: if boot_rom { 0x00 } else { model.divider().unwrap_or(0x00) },
             interrupt: false,
             interrupt_served: false,
         }
@@ -85,6 +82,13 @@ impl Timer {
         interrupt
     }
 
+    /// Resets DIV, which may increase TIMA on the falling edge of the selected bit.
+    pub fn reset_divider(&mut self) {
+        let old_ticks = self.ticks;
+        self.ticks = 0x00;
+        self.tima_increase(old_ticks);
+    }
+
     fn tima_increase(&mut self, old_ticks: u16) {
         if self.timer_enabled() && self.timer_increase(old_ticks) {
             let (new_tima, overflow) = self.tima.overflowing_add(1);
@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::str::FromStr;
+
+use crate::logger::Logger;
+use crate::mmu::MemoryManagementUnit;
+use crate::register::Register;
+use crate::register::RegisterId::{A, B, C, D, E, H, L};
+use crate::trace::TraceState::{Finished, Tracing, Waiting};
+
+/// Condition used to start or stop an instruction trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TraceTrigger {
+    /// Triggers when the instruction at the given address is about to be executed.
+    Pc(u16),
+    /// Triggers when the instruction at the given symbol is about to be executed.
+    Label(String),
+    /// Triggers once the given amount of frames has been rendered.
+    Frame(usize),
+}
+
+impl TraceTrigger {
+    pub fn matches(&self, pc: u16, mmu: &MemoryManagementUnit) -> bool {
+        match self {
+            TraceTrigger::Pc(address) => *address == pc,
+            TraceTrigger::Label(label) => mmu.label_address(label) == Some(pc),
+            TraceTrigger::Frame(frame) => mmu.frames >= *frame,
+        }
+    }
+}
+
+impl FromStr for TraceTrigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| {
+                format!("Expected pc:<address>, label:<symbol> or frame:<number>, got {s}")
+            })?;
+
+        match kind.to_ascii_lowercase().as_str() {
+            "pc" => {
+                let value = value.trim_start_matches("0x").trim_start_matches('$');
+                u16::from_str_radix(value, 16)
+                    .map(TraceTrigger::Pc)
+                    .map_err(|e| format!("Invalid PC trigger {value}: {e}"))
+            }
+            "label" if value.is_empty() => Err("Missing symbol for label trigger".to_string()),
+            "label" => Ok(TraceTrigger::Label(value.to_string())),
+            "frame" => value
+                .parse()
+                .map(TraceTrigger::Frame)
+                .map_err(|e| format!("Invalid frame trigger {value}: {e}")),
+            _ => Err(format!("Unknown trace trigger: {kind}")),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum TraceState {
+    Waiting,
+    Tracing,
+    Finished,
+}
+
+/// Writes one line per executed instruction using the Gameboy Doctor log format:
+/// `A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0000 PCMEM:00,00,00,00`
+pub struct Tracer {
+    /// Appends the symbol at PC to each line. Breaks line by line comparison with Gameboy Doctor.
+    pub labels: bool,
+    output: Box<dyn Write + Send>,
+    start: Option<TraceTrigger>,
+    stop: Option<TraceTrigger>,
+    state: TraceState,
+}
+
+impl Tracer {
+    pub fn new(
+        output: Box<dyn Write + Send>,
+        start: Option<TraceTrigger>,
+        stop: Option<TraceTrigger>,
+    ) -> Self {
+        Self {
+            labels: false,
+            output,
+            state: if start.is_some() { Waiting } else { Tracing },
+            start,
+            stop,
+        }
+    }
+
+    /// Traces to the given file, or to the standard output if the path is `-`.
+    pub fn to_path(
+        path: &str,
+        start: Option<TraceTrigger>,
+        stop: Option<TraceTrigger>,
+    ) -> std::io::Result<Self> {
+        let output: Box<dyn Write + Send> = if path == "-" {
+            Box::new(BufWriter::new(stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(Self::new(output, start, stop))
+    }
+
+    pub fn trace(&mut self, reg: &Register, mmu: &MemoryManagementUnit) {
+        let pc = reg.pc.value();
+        let triggered = |trigger: &Option<TraceTrigger>| {
+            matches!(trigger, Some(trigger) if trigger.matches(pc, mmu))
+        };
+
+        self.state = match self.state {
+            Waiting if triggered(&self.start) => Tracing,
+            Tracing if triggered(&self.stop) => {
+                self.flush();
+                Finished
+            }
+            state => state,
+        };
+
+        if self.state != Tracing {
+            return;
+        }
+
+        let pc_mem = [0, 1, 2, 3].map(|offset| mmu.internal_read(pc.wrapping_add(offset) as usize));
+
+        let mut line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            reg[A].value,
+            reg.flags.value(),
+            reg[B].value,
+            reg[C].value,
+            reg[D].value,
+            reg[E].value,
+            reg[H].value,
+            reg[L].value,
+            reg.sp.value(),
+            pc,
+            pc_mem[0],
+            pc_mem[1],
+            pc_mem[2],
+            pc_mem[3],
+        );
+
+        if self.labels {
+            if let Some(label) = mmu.symbols.as_ref().and_then(|s| s.label(mmu.bank(pc), pc)) {
+                line += &format!(" ; {label}");
+            }
+        }
+
+        if let Err(e) = writeln!(self.output, "{line}") {
+            Logger::error(format!("Unable to write trace, disabling it: {e}"));
+            self.state = Finished;
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.output.flush() {
+            Logger::error(format!("Unable to flush trace: {e}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pc_triggers() {
+        assert_eq!("pc:0150".parse(), Ok(TraceTrigger::Pc(0x0150)));
+        assert_eq!("PC:0x4000".parse(), Ok(TraceTrigger::Pc(0x4000)));
+        assert_eq!("pc:$C000".parse(), Ok(TraceTrigger::Pc(0xC000)));
+    }
+
+    #[test]
+    fn parses_label_and_frame_triggers() {
+        assert_eq!(
+            "label:Main.loop".parse(),
+            Ok(TraceTrigger::Label("Main.loop".to_string()))
+        );
+        // Only the first colon separates the kind
+        assert_eq!("label:a:b".parse(), Ok(TraceTrigger::Label("a:b".to_string())));
+        assert_eq!("frame:60".parse(), Ok(TraceTrigger::Frame(60)));
+    }
+
+    #[test]
+    fn rejects_bad_triggers() {
+        for trigger in [
+            "0150",
+            "pc:",
+            "pc:xyz",
+            "pc:10000",
+            "label:",
+            "frame:-1",
+            "frame:ten",
+            "line:5",
+        ] {
+            assert!(trigger.parse::<TraceTrigger>().is_err(), "{trigger} was accepted");
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use image::imageops::{overlay, resize, FilterType};
+use image::{ImageResult, Rgba, RgbaImage};
+
+use crate::ppu::{PixelProcessingUnit, PixelSource, Sprite};
+use crate::{HEIGHT, WIDTH};
+
+#[cfg(any(unix, windows))]
+use {
+    crate::logger::Logger,
+    pixels::{Pixels, SurfaceTexture},
+    winit::dpi::LogicalSize,
+    winit::event_loop::EventLoopWindowTarget,
+    winit::window::{Window, WindowBuilder, WindowId},
+};
+
+/// Tiles in each VRAM bank, at 0x8000-0x97FF.
+const TILES: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const MAP_SIZE: u32 = 256;
+
+const SPRITES: usize = 40;
+/// The OAM table lists the sprites in two columns, each row fitting a 8x16 sprite.
+const TABLE_ROWS: usize = SPRITES / 2;
+const TABLE_ROW_HEIGHT: u32 = 18;
+const TABLE_COLUMN_WIDTH: u32 = 72;
+const TABLE_SCALE: u32 = 2;
+
+const VIEWPORT: Rgba<u8> = Rgba([0xFF, 0x30, 0x30, 0xFF]);
+const WINDOW: Rgba<u8> = Rgba([0x30, 0x80, 0xFF, 0xFF]);
+const TABLE_BACKGROUND: Rgba<u8> = Rgba([0x30, 0x30, 0x30, 0xFF]);
+const TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+
+/// 3x5 glyphs of the hex digits, one bit per pixel starting from the top left corner.
+const DIGITS: [u16; 16] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+    0b010_101_111_101_101,
+    0b110_101_110_101_110,
+    0b011_100_100_100_011,
+    0b110_101_101_101_110,
+    0b111_100_111_100_111,
+    0b111_100_111_100_100,
+];
+
+/// Size of the overview shown in the viewer window: the tile maps above the tiles of both
+/// banks, next to the OAM table.
+pub const OVERVIEW_WIDTH: u32 = MAP_SIZE * 2 + TABLE_COLUMN_WIDTH * 2 * TABLE_SCALE;
+pub const OVERVIEW_HEIGHT: u32 = TABLE_ROWS as u32 * TABLE_ROW_HEIGHT * TABLE_SCALE;
+
+/// Color index of a pixel of the tile at `address` in VRAM.
+fn tile_pixel(ppu: &PixelProcessingUnit, address: usize, x: usize, y: usize) -> u8 {
+    let [low, high] = [ppu.vram[address + y * 2], ppu.vram[address + y * 2 + 1]];
+    let bit = 7 - x;
+    ((low >> bit) & 1) | ((high >> bit) & 1) << 1
+}
+
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+/// Color of a pixel from the given layer: through the CGB palette `cgb_palette` on the CGB, and
+/// through the DMG palette register otherwise.
+fn layer_color(ppu: &PixelProcessingUnit, color: u8, source: PixelSource, dmg_palette: u8, cgb_palette: u8) -> Rgba<u8> {
+    let [r, g, b] = if ppu.cgb {
+        ppu.rgb(color, source, cgb_palette)
+    } else {
+        ppu.rgb(shade(dmg_palette, color), source, 0)
+    };
+    Rgba([r, g, b, 0xFF])
+}
+
+fn ramp_color(ramp: &[[u8; 3]; 4], shade: usize) -> Rgba<u8> {
+    let [r, g, b] = ramp[shade];
+    Rgba([r, g, b, 0xFF])
+}
+
+/// The 384 tiles of each VRAM bank, with their raw color indices.
+pub fn tiles(ppu: &PixelProcessingUnit) -> RgbaImage {
+    let banks = if ppu.cgb { 2 } else { 1 };
+    let width = (TILES_PER_ROW * 8 * banks) as u32;
+    let mut image = RgbaImage::new(width, (TILES / TILES_PER_ROW * 8) as u32);
+
+    for bank in 0..banks {
+        for tile in 0..TILES {
+            let address = bank * 0x2000 + tile * 16;
+            let left = bank * TILES_PER_ROW * 8 + (tile % TILES_PER_ROW) * 8;
+            let top = (tile / TILES_PER_ROW) * 8;
+            for (x, y) in (0..8).flat_map(|y| (0..8).map(move |x| (x, y))) {
+                let color = tile_pixel(ppu, address, x, y) as usize;
+                let pixel = ramp_color(&ppu.palette.bg, color);
+                image.put_pixel((left + x) as u32, (top + y) as u32, pixel);
+            }
+        }
+    }
+    image
+}
+
+/// Both 32x32 tile maps side by side, through the background palettes and the tile data area
+/// LCDC selects. The
+/// viewport is outlined on the background map and the visible part of the window on its map.
+pub fn tile_maps(ppu: &PixelProcessingUnit) -> RgbaImage {
+    let mut image = RgbaImage::new(MAP_SIZE * 2, MAP_SIZE);
+
+    for (map, base) in [0x1800, 0x1C00].into_iter().enumerate() {
+        for cell in 0..32 * 32 {
+            let index = ppu.vram[base + cell];
+            let address = if ppu.lcdc & 0x10 != 0 {
+                index as usize * 16
+            } else {
+                (0x1000 + index as i8 as isize * 16) as usize
+            };
+            // CGB tile attributes are stored in bank 1
+            let attributes = if ppu.cgb { ppu.vram[0x2000 + base + cell] } else { 0 };
+            let address = address + if attributes & 0x08 != 0 { 0x2000 } else { 0 };
+
+            for (x, y) in (0..8).flat_map(|y| (0..8).map(move |x| (x, y))) {
+                let tile_x = if attributes & 0x20 != 0 { 7 - x } else { x };
+                let tile_y = if attributes & 0x40 != 0 { 7 - y } else { y };
+                let color = tile_pixel(ppu, address, tile_x, tile_y);
+                let pixel = layer_color(ppu, color, PixelSource::Background, ppu.bgp, attributes & 0x07);
+                let left = map as u32 * MAP_SIZE + (cell % 32 * 8 + x) as u32;
+                image.put_pixel(left, (cell / 32 * 8 + y) as u32, pixel);
+            }
+        }
+    }
+
+    let background_map = ((ppu.lcdc >> 3) & 0x01) as u32;
+    let viewport = (ppu.scx as u32, ppu.scy as u32, WIDTH as u32, HEIGHT as u32);
+    outline(&mut image, background_map, viewport, VIEWPORT);
+
+    let window_enabled = ppu.lcdc & 0x20 != 0 && ppu.wx <= 166 && ppu.wy < HEIGHT as u8;
+    if window_enabled {
+        let window_map = ((ppu.lcdc >> 6) & 0x01) as u32;
+        let width = (167 - ppu.wx as u32).min(WIDTH as u32);
+        let height = HEIGHT as u32 - ppu.wy as u32;
+        outline(&mut image, window_map, (0, 0, width, height), WINDOW);
+    }
+    image
+}
+
+/// Draws a rectangle on a tile map, wrapping around its edges like the PPU does.
+fn outline(image: &mut RgbaImage, map: u32, (x, y, width, height): (u32, u32, u32, u32), color: Rgba<u8>) {
+    let mut put = |dx: u32, dy: u32| {
+        let left = map * MAP_SIZE + (x + dx) % MAP_SIZE;
+        image.put_pixel(left, (y + dy) % MAP_SIZE, color);
+    };
+    for dx in 0..width {
+        put(dx, 0);
+        put(dx, height - 1);
+    }
+    for dy in 0..height {
+        put(0, dy);
+        put(width - 1, dy);
+    }
+}
+
+/// The 40 OAM entries, each with its index, graphic, Y, X, tile and flags in hex.
+pub fn oam_table(ppu: &PixelProcessingUnit) -> RgbaImage {
+    let width = TABLE_COLUMN_WIDTH * 2;
+    let height = TABLE_ROWS as u32 * TABLE_ROW_HEIGHT;
+    let mut image = RgbaImage::from_pixel(width, height, TABLE_BACKGROUND);
+    let tall = ppu.lcdc & 0x04 != 0;
+
+    for (index, entry) in ppu.oam.chunks_exact(4).take(SPRITES).enumerate() {
+        let sprite = Sprite {
+            sy: entry[0],
+            sx: entry[1],
+            tile: entry[2],
+            flags: entry[3],
+            index: index as u8,
+        };
+        let left = (index / TABLE_ROWS) as u32 * TABLE_COLUMN_WIDTH;
+        let top = (index % TABLE_ROWS) as u32 * TABLE_ROW_HEIGHT + 1;
+
+        draw_sprite(&mut image, ppu, &sprite, tall, left + 12, top);
+        let values = [sprite.index, sprite.sy, sprite.sx, sprite.tile, sprite.flags];
+        for (column, value) in values.into_iter().enumerate() {
+            let x = left + 1 + if column == 0 { 0 } else { 12 + column as u32 * 12 };
+            draw_hex(&mut image, value, x, top + 5);
+        }
+    }
+    resize(&image, width * TABLE_SCALE, height * TABLE_SCALE, FilterType::Nearest)
+}
+
+fn draw_sprite(image: &mut RgbaImage, ppu: &PixelProcessingUnit, sprite: &Sprite, tall: bool, left: u32, top: u32) {
+    let height = if tall { 16 } else { 8 };
+    let tile = if tall { sprite.tile & 0xFE } else { sprite.tile } as usize;
+    let bank = if ppu.cgb && sprite.flags & 0x08 != 0 { 0x2000 } else { 0 };
+    let (source, palette) = if sprite.flags & 0x10 != 0 {
+        (PixelSource::Object1, ppu.obp1)
+    } else {
+        (PixelSource::Object0, ppu.obp0)
+    };
+
+    for (x, y) in (0..height).flat_map(|y| (0..8).map(move |x| (x, y))) {
+        let tile_x = if sprite.flags & 0x20 != 0 { 7 - x } else { x };
+        let tile_y = if sprite.flags & 0x40 != 0 { height - 1 - y } else { y };
+        let color = tile_pixel(ppu, bank + tile * 16, tile_x, tile_y);
+        // Color 0 is transparent
+        if color != 0 {
+            let pixel = layer_color(ppu, color, source, palette, sprite.flags & 0x07);
+            image.put_pixel(left + x as u32, top + y as u32, pixel);
+        }
+    }
+}
+
+fn draw_hex(image: &mut RgbaImage, value: u8, left: u32, top: u32) {
+    for (digit, nibble) in [value >> 4, value & 0x0F].into_iter().enumerate() {
+        let glyph = DIGITS[nibble as usize];
+        for bit in 0..15 {
+            if glyph & (1 << (14 - bit)) != 0 {
+                let x = left + digit as u32 * 4 + bit % 3;
+                image.put_pixel(x, top + bit / 3, TEXT);
+            }
+        }
+    }
+}
+
+/// Every view in a single image, as shown in the viewer window.
+pub fn overview(ppu: &PixelProcessingUnit) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(OVERVIEW_WIDTH, OVERVIEW_HEIGHT, TABLE_BACKGROUND);
+    overlay(&mut image, &tile_maps(ppu), 0, 0);
+    overlay(&mut image, &tiles(ppu), 0, MAP_SIZE as i64);
+    overlay(&mut image, &oam_table(ppu), (MAP_SIZE * 2) as i64, 0);
+    image
+}
+
+/// Writes every view to a PNG file next to the ROM.
+pub fn export(ppu: &PixelProcessingUnit, rom_path: &str) -> ImageResult<()> {
+    tiles(ppu).save(format!("{rom_path}.tiles.png"))?;
+    tile_maps(ppu).save(format!("{rom_path}.tilemaps.png"))?;
+    oam_table(ppu).save(format!("{rom_path}.oam.png"))
+}
+
+/// Secondary window showing the overview, redrawn after every frame.
+#[cfg(any(unix, windows))]
+pub struct ViewerWindow {
+    // Dropped before the window it draws to
+    pixels: Pixels,
+    window: Window,
+}
+
+#[cfg(any(unix, windows))]
+impl ViewerWindow {
+    pub fn open<T>(target: &EventLoopWindowTarget<T>, rom_path: &str) -> Option<Self> {
+        let size = LogicalSize::new(OVERVIEW_WIDTH, OVERVIEW_HEIGHT);
+        let window = WindowBuilder::new()
+            .with_title(format!("{rom_path} - VRAM"))
+            .with_inner_size(size)
+            .build(target)
+            .map_err(|e| Logger::error(format!("Unable to open VRAM viewer: {e}")))
+            .ok()?;
+        let surface = SurfaceTexture::new(OVERVIEW_WIDTH, OVERVIEW_HEIGHT, &window);
+        let pixels = Pixels::new(OVERVIEW_WIDTH, OVERVIEW_HEIGHT, surface)
+            .map_err(|e| Logger::error(format!("Unable to open VRAM viewer: {e:?}")))
+            .ok()?;
+        Some(Self { pixels, window })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.pixels.resize_surface(width, height).unwrap();
+    }
+
+    pub fn draw(&mut self, ppu: &PixelProcessingUnit) {
+        self.pixels.get_frame_mut().copy_from_slice(&overview(ppu));
+        self.pixels.render().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::RenderMode;
+
+    #[test]
+    fn tiles_show_every_bank() {
+        let mut ppu = PixelProcessingUnit::new(RenderMode::Fifo);
+        assert_eq!(tiles(&ppu).dimensions(), (128, 192));
+        ppu.cgb = true;
+        assert_eq!(tiles(&ppu).dimensions(), (256, 192));
+    }
+
+    #[test]
+    fn outline_wraps_around_map_edges() {
+        let mut image = RgbaImage::new(MAP_SIZE * 2, MAP_SIZE);
+        outline(&mut image, 1, (250, 252, 10, 8), VIEWPORT);
+
+        let drawn = |x: u32, y: u32| image.get_pixel(MAP_SIZE + x, y) == &VIEWPORT;
+        assert!(drawn(250, 252) && drawn(3, 252) && drawn(250, 3) && drawn(3, 3));
+        assert!(drawn(255, 252) && drawn(0, 3));
+        assert!(!drawn(4, 252) && !drawn(250, 4) && !drawn(1, 1));
+        // The other map is left alone
+        assert!(image.enumerate_pixels().all(|(x, _, pixel)| x >= MAP_SIZE || pixel != &VIEWPORT));
+    }
+
+    #[test]
+    fn cgb_tile_maps_use_attribute_palette() {
+        let mut ppu = PixelProcessingUnit::new(RenderMode::Fifo);
+        ppu.cgb = true;
+        ppu.lcdc = 0x91;
+        // Palette 2 color 0 is pure red, the first cell of the background map uses it
+        ppu.bg_palettes[0x10..0x12].copy_from_slice(&0x001Fu16.to_le_bytes());
+        ppu.vram[0x3800] = 0x02;
+
+        let image = tile_maps(&ppu);
+        assert_eq!(image.get_pixel(1, 1), &Rgba([0xFF, 0x00, 0x00, 0xFF]));
+        assert_eq!(image.get_pixel(9, 1), &Rgba([0xFF, 0xFF, 0xFF, 0xFF]));
+    }
+}